@@ -18,6 +18,7 @@ ExclusiveArch:  %{rust_arches}
 BuildRequires:  rust-packaging
 BuildRequires:  systemd-rpm-macros
 
+Requires:       kmod
 Requires:       mokutil
 Requires:       podman
 
@@ -43,6 +44,12 @@ Summary:        %{summary}
 %{_mandir}/
 %{_sysconfdir}/podmod.conf
 %{_unitdir}/podmod@.service
+%{_unitdir}/podmod-sync.service
+%{_unitdir}/podmod-sync.path
+%{_unitdir}/podmod-sync.timer
+%{_systemdgeneratordir}/podmod-generator
+%{_prefix}/lib/kernel/install.d/50-podmod.install
+%dir %{_sharedstatedir}/podmod/
 
 %package        devel
 Summary:        %{summary}
@@ -88,6 +95,9 @@ mkdir -p %{buildroot}%{_datadir}/fish/vendor_completions.d/
 mkdir -p %{buildroot}%{_mandir}/man8/ %{buildroot}%{_mandir}/man5/
 mkdir -p %{buildroot}%{_sysconfdir}
 mkdir -p %{buildroot}%{_unitdir}
+mkdir -p %{buildroot}%{_systemdgeneratordir}
+mkdir -p %{buildroot}%{_prefix}/lib/kernel/install.d/
+mkdir -p %{buildroot}%{_sharedstatedir}/podmod/
 cp -pr share/ %{buildroot}%{_datadir}/podmod/
 install -p -m0644 %{cargo_out_dir}/podmod.bash %{buildroot}%{_datadir}/bash-completion/completions/podmod
 install -p -m0644 %{cargo_out_dir}/_podmod %{buildroot}%{_datadir}/zsh/site-functions/_podmod
@@ -96,6 +106,11 @@ install -p -m0644 docs/*.8 %{buildroot}%{_mandir}/man8/
 install -p -m0644 docs/*.5 %{buildroot}%{_mandir}/man5/
 install -p -m0644 extra/podmod.conf.dist %{buildroot}%{_sysconfdir}/podmod.conf
 install -p -m0644 extra/podmod@.service %{buildroot}%{_unitdir}
+install -p -m0644 extra/podmod-sync.service %{buildroot}%{_unitdir}
+install -p -m0644 extra/podmod-sync.path %{buildroot}%{_unitdir}
+install -p -m0644 extra/podmod-sync.timer %{buildroot}%{_unitdir}
+install -p -m0755 extra/podmod-generator %{buildroot}%{_systemdgeneratordir}
+install -p -m0755 extra/50-podmod.install %{buildroot}%{_prefix}/lib/kernel/install.d/
 
 %if %{with check}
 %check
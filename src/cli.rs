@@ -23,6 +23,14 @@ pub struct CLI {
     #[clap(short, long, default_value = "/etc/podmod.conf")]
     pub config: String,
 
+    /// Wait for other podmod processes working on the same module or images [default]
+    #[clap(long, global = true, overrides_with = "no-wait")]
+    pub wait: bool,
+
+    /// Fail instead of waiting for other podmod processes
+    #[clap(long, global = true, overrides_with = "wait")]
+    pub no_wait: bool,
+
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -35,6 +43,10 @@ pub enum Command {
         #[clap(short, long)]
         idempotent: bool,
 
+        /// The kernel version to build the module for [default: running kernel]
+        #[clap(short, long)]
+        kernel: Option<String>,
+
         /// The module to work on
         #[clap(short, long)]
         module: String,
@@ -44,8 +56,54 @@ pub enum Command {
         no_prune: bool,
     },
 
+    /// Copy the built kernel objects out of the module image
+    Export {
+        /// The kernel version to export the module for [default: running kernel]
+        #[clap(short, long)]
+        kernel: Option<String>,
+
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+
+        /// The directory to copy the files into
+        #[clap(short, long)]
+        output: String,
+
+        /// Also copy the module's userland binaries
+        #[clap(long)]
+        userland: bool,
+    },
+
+    /// Generate systemd units for modules marked 'autoload'
+    GenerateUnits {
+        /// The directory to write the units into
+        normal_dir: String,
+
+        /// Ignored, for compatibility with systemd.generator(7)
+        early_dir: Option<String>,
+
+        /// Ignored, for compatibility with systemd.generator(7)
+        late_dir: Option<String>,
+    },
+
+    /// Show information on the built kernel objects
+    Info {
+        /// Print the information as JSON
+        #[clap(long)]
+        json: bool,
+
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+    },
+
     /// Load the kernel module
     Load {
+        /// Build the module first if it isn't built for the running kernel
+        #[clap(short, long)]
+        build: bool,
+
         /// Quietly exit if module is already loaded
         #[clap(short, long)]
         idempotent: bool,
@@ -53,34 +111,151 @@ pub enum Command {
         /// The module to work on
         #[clap(short, long)]
         module: String,
+
+        /// Override or add a kernel parameter for this invocation only
+        #[clap(short, long = "param", value_name = "KEY=VALUE")]
+        params: Vec<String>,
+
+        /// Ignore the kernel parameters from the configuration file
+        #[clap(long)]
+        replace_params: bool,
+
+        /// Additional kernel parameters, same as '--param'
+        #[clap(last = true, value_name = "KEY=VALUE")]
+        args: Vec<String>,
     },
 
     /// List supported kernel modules
     Modules {},
 
+    /// List or change the runtime parameters of the loaded module
+    Params {
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+
+        #[clap(subcommand)]
+        action: Option<ParamsAction>,
+    },
+
+    /// Unload and load the kernel module again
+    Reload {
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+
+        /// Restore the current values of writable parameters after reloading
+        #[clap(long)]
+        preserve_params: bool,
+    },
+
     /// Run a command inside a new container
     Run {
+        /// Set an environment variable inside the container
+        #[clap(short, long, value_name = "KEY=VALUE")]
+        env: Vec<String>,
+
+        /// Read environment variables from a file
+        #[clap(long)]
+        env_file: Vec<String>,
+
+        /// Keep stdin open even if nothing is connected to it
+        #[clap(long)]
+        interactive: bool,
+
         /// The module to work on
         #[clap(short, long)]
         module: String,
 
+        /// Mount the current directory into the container and use it as the working directory
+        #[clap(long)]
+        mount_cwd: bool,
+
+        /// Don't allocate a pseudo-TTY
+        #[clap(long)]
+        no_tty: bool,
+
+        /// Don't print the command before executing it
+        #[clap(short, long)]
+        quiet: bool,
+
+        /// Allocate a pseudo-TTY [default: if stdin and stdout are terminals]
+        #[clap(long, conflicts_with = "no-tty")]
+        tty: bool,
+
+        /// The working directory inside the container
+        #[clap(short, long)]
+        workdir: Option<String>,
+
         /// The command to execute
         command: Vec<String>,
     },
 
     /// Start a shell session inside a new container
     Shell {
+        /// Set an environment variable inside the container
+        #[clap(short, long, value_name = "KEY=VALUE")]
+        env: Vec<String>,
+
+        /// Read environment variables from a file
+        #[clap(long)]
+        env_file: Vec<String>,
+
+        /// Keep stdin open even if nothing is connected to it
+        #[clap(long)]
+        interactive: bool,
+
         /// The module to work on
         #[clap(short, long)]
         module: String,
 
+        /// Mount the current directory into the container and use it as the working directory
+        #[clap(long)]
+        mount_cwd: bool,
+
+        /// Don't allocate a pseudo-TTY
+        #[clap(long)]
+        no_tty: bool,
+
+        /// Allocate a pseudo-TTY [default: if stdin and stdout are terminals]
+        #[clap(long, conflicts_with = "no-tty")]
+        tty: bool,
+
+        /// The working directory inside the container
+        #[clap(short, long)]
+        workdir: Option<String>,
+
         /// The shell command to run
         #[clap(default_value = "/bin/bash")]
         shell: String,
     },
 
+    /// Manage host commands that run a module's userland tools
+    Shim {
+        #[clap(subcommand)]
+        action: ShimAction,
+    },
+
+    /// Show which kernel objects of the module are loaded
+    Status {
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+    },
+
+    /// Build missing modules for all installed kernels
+    Sync {
+        /// Don't prune old images after building
+        #[clap(long)]
+        no_prune: bool,
+    },
+
     /// Unload the kernel module
     Unload {
+        /// Unload the module even if it is in use
+        #[clap(short, long)]
+        force: bool,
+
         /// Quietly exit if module is not loaded
         #[clap(short, long)]
         idempotent: bool,
@@ -90,3 +265,57 @@ pub enum Command {
         module: String,
     },
 }
+
+#[derive(Subcommand, Debug)]
+pub enum ParamsAction {
+    /// Change writable parameters through sysfs
+    Set {
+        /// Also store the new values in the configuration file
+        #[clap(long)]
+        persist: bool,
+
+        /// The parameters to change
+        #[clap(required = true, value_name = "KEY=VALUE")]
+        assignments: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ShimAction {
+    /// Install shims for commands inside the module image
+    Install {
+        /// The command to install a shim for
+        #[clap(short, long = "command", required = true)]
+        commands: Vec<String>,
+
+        /// The directory to install the shims into [default: shim_dir from configuration file]
+        #[clap(short, long)]
+        dir: Option<String>,
+
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+    },
+
+    /// List installed shims
+    List {
+        /// The directory to search for shims [default: shim_dir from configuration file]
+        #[clap(short, long)]
+        dir: Option<String>,
+    },
+
+    /// Remove installed shims
+    Remove {
+        /// The command to remove the shim for [default: all commands]
+        #[clap(short, long = "command")]
+        commands: Vec<String>,
+
+        /// The directory to remove the shims from [default: shim_dir from configuration file]
+        #[clap(short, long)]
+        dir: Option<String>,
+
+        /// The module to work on
+        #[clap(short, long)]
+        module: String,
+    },
+}
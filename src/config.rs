@@ -16,31 +16,325 @@
 use toml;
 use std::collections;
 use std::fs;
+use std::path;
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub data_dir: String,
+    pub cache_dir: String,
+    pub shim_dir: String,
     pub tree: toml::Value,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Load,
+    Run,
+    Shell,
+    Unload,
+}
+
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub path: String,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mount {
+    pub src: String,
+    pub dst: String,
+    pub ro: bool,
+    pub optional: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct ModuleConfig {
     pub name: String,
+    pub description: String,
     pub version: String,
     pub container_args: Vec<String>,
+    pub load_args: Vec<String>,
+    pub run_args: Vec<String>,
+    pub shell_args: Vec<String>,
+    pub unload_args: Vec<String>,
     pub kernel_args: Vec<String>,
     pub build_args: collections::HashMap<String, String>,
+    pub env: collections::HashMap<String, String>,
+    pub required_build_args: Vec<String>,
+    pub objects: Vec<String>,
+    pub binaries: Vec<String>,
+    pub devices: Vec<Device>,
+    pub mounts: Vec<Mount>,
+    pub min_kernel: Option<String>,
+    pub max_kernel: Option<String>,
+    pub scripts: bool,
+    pub privileged: bool,
+    pub run_privileged: bool,
+    pub shell_privileged: bool,
+    pub capabilities: Vec<String>,
+    pub run_capabilities: Vec<String>,
+    pub shell_capabilities: Vec<String>,
+    pub autoload: bool,
+    pub build_on_load: bool,
+    pub depends: Vec<String>,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+pub fn object_name(object: &str) -> String {
+    // The kernel refers to modules by their file name without the '.ko'
+    // extension, and always uses underscores instead of dashes
+    let name = object.rsplit('/').next().unwrap_or(object);
+    let name = name.strip_suffix(".ko").unwrap_or(name);
+
+    name.replace('-', "_")
+}
+
+pub fn container_args(module: &ModuleConfig, operation: Operation) -> Vec<String> {
+    // Arguments common to all operations come first, except when loading,
+    // since older configuration files bind-mount devices that don't exist yet
+    let specific = match operation {
+        Operation::Load => return module.load_args.clone(),
+        Operation::Run => &module.run_args,
+        Operation::Shell => &module.shell_args,
+        Operation::Unload => &module.unload_args,
+    };
+
+    module.container_args
+        .iter()
+        .chain(specific)
+        .cloned()
+        .collect()
+}
+
+pub fn capabilities(module: &ModuleConfig, operation: Operation) -> Vec<String> {
+    // Loading and unloading kernel objects never needs more than CAP_SYS_MODULE
+    let specific = match operation {
+        Operation::Load | Operation::Unload => return vec![String::from("SYS_MODULE")],
+        Operation::Run => &module.run_capabilities,
+        Operation::Shell => &module.shell_capabilities,
+    };
+
+    module.capabilities
+        .iter()
+        .chain(specific)
+        .cloned()
+        .collect()
+}
+
+pub fn privileged(module: &ModuleConfig, operation: Operation) -> bool {
+    // Only the userland tools might need full access to the host
+    module.privileged || match operation {
+        Operation::Load | Operation::Unload => false,
+        Operation::Run => module.run_privileged,
+        Operation::Shell => module.shell_privileged,
+    }
+}
+
+pub fn kernel_arg_name(arg: &str) -> &str {
+    arg.split('=').next().unwrap_or(arg)
+}
+
+pub fn set_kernel_arg(args: &mut Vec<String>, arg: String) {
+    // Replace a previous value of the same parameter, if any
+    let name = kernel_arg_name(&arg);
+
+    match args.iter().position(|a| kernel_arg_name(a) == name) {
+        Some(i) => args[i] = arg,
+        None => args.push(arg),
+    }
+}
+
+fn render_param(value: &toml::Value, what: &str) -> String {
+    match value {
+        // The kernel accepts booleans in the same format it reports them in sysfs
+        toml::Value::Boolean(true) => String::from("Y"),
+        toml::Value::Boolean(false) => String::from("N"),
+        toml::Value::Integer(i) => i.to_string(),
+        // Values with whitespace must be quoted, just like on the kernel command line
+        toml::Value::String(s) if s.contains(char::is_whitespace) => format!("\"{}\"", s),
+        toml::Value::String(s) => s.clone(),
+        // Array parameters are comma-separated
+        toml::Value::Array(array) => array
+            .iter()
+            .map(|v| match v {
+                toml::Value::Array(_) => panic!("{} must not contain nested arrays", what),
+                v => render_param(v, what),
+            })
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => panic!("{} must be a boolean, integer, string, or array value", what),
+    }
+}
+
+pub fn object_kernel_args(module: &ModuleConfig, object: &str) -> Vec<String> {
+    let name = object_name(object);
+    let primary = module.objects.last().map(|o| object_name(o));
+
+    // Kernel parameters prefixed with 'OBJECT.' apply to that kernel object only,
+    // just like on the kernel command line. Any other parameter applies to the
+    // last kernel object, which is the one providing the module itself
+    module.kernel_args
+        .iter()
+        .filter_map(|arg| {
+            let prefixed = arg
+                .split_once('.')
+                .filter(|(prefix, _)| !prefix.contains('='))
+                .filter(|(prefix, _)| module.objects.iter().any(|o| object_name(o) == *prefix));
+
+            match prefixed {
+                Some((prefix, arg)) if *prefix == name => Some(String::from(arg)),
+                Some(_) => None,
+                None if primary.as_deref() == Some(name.as_str()) => Some(arg.clone()),
+                None => None,
+            }
+        })
+        .collect()
+}
+
+pub fn split_param_key(module: &ModuleConfig, key: &str) -> (String, String) {
+    // Parameters are addressed the same way as in 'kernel_args', i.e. prefixed
+    // with 'OBJECT.' for any kernel object other than the last one
+    if let Some((prefix, param)) = key.split_once('.') {
+        if module.objects.iter().any(|o| object_name(o) == prefix) {
+            return (String::from(prefix), String::from(param));
+        }
+    }
+
+    let primary = module.objects.last().map(|o| object_name(o)).unwrap_or_default();
+
+    (primary, String::from(key))
+}
+
+fn render_toml_key(key: &str) -> String {
+    // Bare keys may only contain ASCII letters, digits, dashes, and underscores
+    if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        String::from(key)
+    } else {
+        toml::Value::String(String::from(key)).to_string()
+    }
+}
+
+fn normalize_toml_key(key: &str) -> String {
+    // Keys may be dotted, with whitespace around the dots and quoted parts
+    key.split('.')
+        .map(|part| part.trim().trim_matches('"').trim_matches('\''))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn table_header(line: &str) -> Option<String> {
+    let line = line.split('#').next().unwrap_or_default().trim();
+
+    // Arrays of tables never hold parameters, but still end the previous table
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let inner = inner.strip_prefix('[').and_then(|i| i.strip_suffix(']')).unwrap_or(inner);
+
+    Some(normalize_toml_key(inner))
+}
+
+fn line_key(line: &str) -> Option<String> {
+    if line.trim_start().starts_with('#') {
+        return None;
+    }
+
+    line.split_once('=').map(|(key, _)| normalize_toml_key(key))
+}
+
+fn param_value<'a>(tree: &'a toml::Value, module: &str, key: &str) -> Option<&'a toml::Value> {
+    let params = tree.get(module)?.get("params")?;
+
+    // Object-scoped keys are written as dotted keys, see 'edit_param'
+    match key.split_once('.') {
+        Some((object, param)) => params.get(object)?.get(param),
+        None => params.get(key),
+    }
+}
+
+pub fn edit_param(file: &str, module: &str, key: &str, value: &str) -> Result<String, String> {
+    let tree = file
+        .parse::<toml::Value>()
+        .map_err(|_| String::from("the file is not valid TOML"))?;
+
+    // Store integers as such, and anything else as a string
+    let value = match value.parse::<i64>() {
+        Ok(i) => toml::Value::Integer(i),
+        Err(_) => toml::Value::String(String::from(value)),
+    };
+
+    let table = format!("{}.params", module);
+    let rendered_key = key.split('.').map(render_toml_key).collect::<Vec<_>>().join(".");
+    let line = format!("{} = {}", rendered_key, value);
+
+    // Edit the file line by line, so that comments and formatting are preserved
+    let mut lines: Vec<String> = file.lines().map(String::from).collect();
+
+    match lines.iter().position(|l| table_header(l).as_deref() == Some(table.as_str())) {
+        Some(start) => {
+            // The table ends at the next header, or at the end of the file
+            let end = lines[start + 1..]
+                .iter()
+                .position(|l| table_header(l).is_some())
+                .map(|i| start + 1 + i)
+                .unwrap_or(lines.len());
+
+            match (start + 1..end).find(|&i| line_key(&lines[i]).as_deref() == Some(key)) {
+                Some(i) => lines[i] = line,
+                None => {
+                    // Insert after the last non-blank line of the table
+                    let last = (start..end).rev().find(|&i| !lines[i].trim().is_empty()).unwrap_or(start);
+                    lines.insert(last + 1, line);
+                }
+            }
+        }
+        // Parameters defined inline or with dotted keys elsewhere can't be edited safely
+        None if tree.get(module).and_then(|m| m.get("params")).is_some() => {
+            return Err(format!("parameters of module {} are not defined in a [{}] table", module, table));
+        }
+        None => {
+            lines.push(String::new());
+            lines.push(format!("[{}]", table));
+            lines.push(line);
+        }
+    }
+
+    let mut edited = lines.join("\n");
+    edited.push('\n');
+
+    // Never leave a broken configuration file behind, or one that doesn't have the intended effect
+    let edited_tree = edited
+        .parse::<toml::Value>()
+        .map_err(|_| format!("setting {} would produce invalid TOML", key))?;
+
+    if param_value(&edited_tree, module, key) != Some(&value) {
+        return Err(format!("setting {} would not take effect", key));
+    }
+
+    Ok(edited)
+}
+
+pub fn edit_params(path: &str, module: &str, assignments: &[(&str, &str)]) -> String {
+    let mut file = fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Error while reading configuration file at {}", path));
+
+    for (key, value) in assignments {
+        file = edit_param(&file, module, key, value)
+            .unwrap_or_else(|e| panic!("Error while updating configuration file at {} ({})", path, e));
+    }
+
+    file
 }
 
 pub fn parse(path: &str) -> Config {
     // Read file into String
     let file = fs::read_to_string(path)
-        .expect(&format!("Error while reading configuration file at {}", path));
+        .unwrap_or_else(|_| panic!("Error while reading configuration file at {}", path));
 
     // Parse file using the 'toml' crate
     let config = file
         .parse::<toml::Value>()
-        .expect(&format!("Error while parsing configuration file at {}", path));
+        .unwrap_or_else(|_| panic!("Error while parsing configuration file at {}", path));
 
     // Fetch TOML values
     let data_dir = config
@@ -51,83 +345,550 @@ pub fn parse(path: &str) -> Config {
 
     let data_dir = String::from(data_dir);
 
+    let cache_dir = config
+        .get("cache_dir")
+        .map(|v| v.as_str().expect("Configuration option 'cache_dir' must have a string value"))
+        .unwrap_or("/var/lib/podmod");
+
+    let cache_dir = String::from(cache_dir);
+
+    let shim_dir = config
+        .get("shim_dir")
+        .map(|v| v.as_str().expect("Configuration option 'shim_dir' must have a string value"))
+        .unwrap_or("/usr/local/bin");
+
+    let shim_dir = String::from(shim_dir);
+
     Config {
         data_dir,
+        cache_dir,
+        shim_dir,
         tree: config,
     }
 }
 
-pub fn module(config: &toml::Value, module: &str) -> ModuleConfig {
-    // Fetch parent TOML tables
-    let config = config
+pub fn manifest(data_dir: &str, module: &str) -> Option<toml::value::Table> {
+    // Each module may ship a manifest next to its Containerfile
+    let path = format!("{}/modules/{}/module.toml", data_dir, module);
+
+    if !path::Path::new(&path).is_file() {
+        return None;
+    }
+
+    let file = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("Error while reading module manifest at {}", path));
+
+    let manifest = file
+        .parse::<toml::Value>()
+        .unwrap_or_else(|_| panic!("Error while parsing module manifest at {}", path));
+
+    match manifest {
+        toml::Value::Table(table) => Some(table),
+        _ => panic!("Module manifest at {} must be a table", path),
+    }
+}
+
+fn merge(base: &mut toml::value::Table, overlay: &toml::value::Table) {
+    for (key, value) in overlay {
+        // Merge sub-tables key by key, so that the user only needs to
+        // specify the values that differ from the manifest
+        if let (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) = (base.get_mut(key), value) {
+            merge(base, overlay);
+            continue;
+        }
+
+        base.insert(key.clone(), value.clone());
+    }
+}
+
+fn get_string(config: &toml::value::Table, key: &str, what: &str) -> Option<String> {
+    config.get(key).map(|v| {
+        let v = v
+            .as_str()
+            .unwrap_or_else(|| panic!("{} must have a string value", what));
+
+        String::from(v)
+    })
+}
+
+fn get_bool(config: &toml::value::Table, key: &str, what: &str) -> Option<bool> {
+    config.get(key).map(|v| {
+        v.as_bool()
+            .unwrap_or_else(|| panic!("{} '{}' must have a boolean value", what, key))
+    })
+}
+
+fn get_entries(config: &toml::value::Table, key: &str, string_key: &str, what: &str) -> Vec<toml::value::Table> {
+    let array = match config.get(key) {
+        Some(array) => array
+            .as_array()
+            .unwrap_or_else(|| panic!("{} must be an array", what)),
+        None => return Vec::new(),
+    };
+
+    // Each entry is either a plain string, or a table with additional options
+    array
+        .iter()
+        .map(|v| match v {
+            toml::Value::String(s) => {
+                let mut table = toml::value::Table::new();
+                table.insert(String::from(string_key), toml::Value::String(s.clone()));
+                table
+            }
+            toml::Value::Table(table) => table.clone(),
+            _ => panic!("{} must be strings or tables", what),
+        })
+        .collect()
+}
+
+fn get_string_array(config: &toml::value::Table, key: &str, what: &str) -> Vec<String> {
+    let array = match config.get(key) {
+        Some(array) => array
+            .as_array()
+            .unwrap_or_else(|| panic!("{} must be an array", what)),
+        None => return Vec::new(),
+    };
+
+    array
+        .iter()
+        .map(|v| v.as_str().unwrap_or_else(|| panic!("{} must have string values", what)))
+        .map(String::from)
+        .collect()
+}
+
+pub fn module(config: &Config, module: &str) -> ModuleConfig {
+    // Settings from the module's manifest act as defaults
+    let manifest = manifest(&config.data_dir, module);
+
+    // Fetch parent TOML table
+    let user_config = config
+        .tree
         .get(module)
-        .expect(&format!("Missing configuration for {} module", module))
-        .as_table()
-        .expect(&format!("Configuration for {} module must be a table", module));
+        .map(|v| {
+            v.as_table()
+                .unwrap_or_else(|| panic!("Configuration for {} module must be a table", module))
+        });
+
+    // User settings from the configuration file take precedence
+    let config = match (manifest, user_config) {
+        (Some(mut manifest), Some(user_config)) => {
+            merge(&mut manifest, user_config);
+            manifest
+        }
+        (Some(manifest), None) => manifest,
+        (None, Some(user_config)) => user_config.clone(),
+        (None, None) => panic!("Missing configuration for {} module", module),
+    };
 
-    let build_config = config
-        .get("build")
-        .expect(&format!("Missing build configuration for {} module", module))
-        .as_table()
-        .expect(&format!("Build configuration for {} module must be a table", module));
+    let build_config = match config.get("build") {
+        Some(build_config) => build_config
+            .as_table()
+            .unwrap_or_else(|| panic!("Build configuration for {} module must be a table", module))
+            .clone(),
+        None => toml::value::Table::new(),
+    };
 
     // Fetch TOML values
     let name = String::from(module);
 
-    let version = config
-        .get("version")
-        .expect(&format!("No version specified for {} module", module))
-        .as_str()
-        .expect(&format!("Version identifier for {} module must have a string value", module));
+    let description = get_string(&config, "description", &format!("Description of {} module", module))
+        .unwrap_or_default();
 
-    let version = String::from(version);
+    let version = get_string(&config, "version", &format!("Version identifier for {} module", module))
+        .unwrap_or_else(|| panic!("No version specified for {} module", module));
 
-    let container_args = toml::value::Value::try_from(Vec::<String>::new()).unwrap();
-    let container_args = config
-        .get("container_args")
-        .unwrap_or(&container_args)
-        .as_array()
-        .expect(&format!("Container arguments for {} module must be an array", module));
+    let container_args = get_string_array(
+        &config,
+        "container_args",
+        &format!("Container arguments for {} module", module),
+    );
 
-    let msg = format!("Container argument for {} module must have a string value", module);
-    let container_args: Vec<_> = container_args
-        .iter()
-        .map(|v| v.as_str().expect(&msg))
-        .map(|v| String::from(v))
-        .collect();
+    let load_args = get_string_array(
+        &config,
+        "load_args",
+        &format!("Container arguments for loading {} module", module),
+    );
 
-    let kernel_args = toml::value::Value::try_from(Vec::<String>::new()).unwrap();
-    let kernel_args = config
-        .get("kernel_args")
-        .unwrap_or(&kernel_args)
-        .as_array()
-        .expect(&format!("Kernel parameters for {} module must be an array", module));
+    let run_args = get_string_array(
+        &config,
+        "run_args",
+        &format!("Container arguments for running commands in {} module", module),
+    );
 
-    let msg = format!("Kernel parameter for {} module must have a string value", module);
-    let kernel_args: Vec<_> = kernel_args
-        .iter()
-        .map(|v| v.as_str().expect(&msg))
-        .map(|v| String::from(v))
-        .collect();
+    let shell_args = get_string_array(
+        &config,
+        "shell_args",
+        &format!("Container arguments for shell sessions in {} module", module),
+    );
+
+    let unload_args = get_string_array(
+        &config,
+        "unload_args",
+        &format!("Container arguments for unloading {} module", module),
+    );
+
+    let mut kernel_args = get_string_array(
+        &config,
+        "kernel_args",
+        &format!("Kernel parameters for {} module", module),
+    );
 
     let mut build_args = collections::HashMap::new();
 
     for (key, value) in build_config {
         let value = value
             .as_str()
-            .expect(&format!("Build parameter for {} module must have a string value", module));
+            .unwrap_or_else(|| panic!("Build parameter for {} module must have a string value", module));
 
-        let key = String::from(key);
         let value = String::from(value);
 
         build_args.insert(key, value);
     }
 
+    let mut env = collections::HashMap::new();
+
+    if let Some(env_config) = config.get("env") {
+        let env_config = env_config
+            .as_table()
+            .unwrap_or_else(|| panic!("Environment for {} module must be a table", module));
+
+        for (key, value) in env_config {
+            let value = value
+                .as_str()
+                .unwrap_or_else(|| panic!("Environment variable for {} module must have a string value", module));
+
+            env.insert(key.clone(), String::from(value));
+        }
+    }
+
+    let required_build_args = get_string_array(
+        &config,
+        "required_build_args",
+        &format!("Required build arguments for {} module", module),
+    );
+
+    let mut objects = get_string_array(
+        &config,
+        "objects",
+        &format!("Kernel objects for {} module", module),
+    );
+
+    // Modules without a list of kernel objects provide a single one named after themselves
+    if objects.is_empty() {
+        objects.push(format!("{}.ko", module));
+    }
+
+    // Structured parameters take precedence over 'kernel_args' of the same name
+    if let Some(params) = config.get("params") {
+        let params = params
+            .as_table()
+            .unwrap_or_else(|| panic!("Parameters for {} module must be a table", module));
+
+        for (key, value) in params {
+            // A dotted key like 'spl.spl_hostid' is parsed as a table named after the kernel object
+            let object_params = match value {
+                toml::Value::Table(object_params) => object_params,
+                value => {
+                    let value = render_param(value, &format!("Parameter '{}' for {} module", key, module));
+                    set_kernel_arg(&mut kernel_args, format!("{}={}", key, value));
+                    continue;
+                }
+            };
+
+            if !objects.iter().any(|o| object_name(o) == *key) {
+                panic!("Parameters for {} module contain a table '{}', which is not one of its kernel objects", module, key);
+            }
+
+            for (param, value) in object_params {
+                let value = render_param(value, &format!("Parameter '{}.{}' for {} module", key, param, module));
+                set_kernel_arg(&mut kernel_args, format!("{}.{}={}", key, param, value));
+            }
+        }
+    }
+
+    let binaries = get_string_array(
+        &config,
+        "binaries",
+        &format!("Userland binaries for {} module", module),
+    );
+
+    let devices: Vec<_> = get_entries(&config, "devices", "path", &format!("Devices for {} module", module))
+        .iter()
+        .map(|device| {
+            let what = format!("Device option for {} module", module);
+
+            let path = get_string(device, "path", &what)
+                .unwrap_or_else(|| panic!("Device for {} module is missing 'path'", module));
+
+            let optional = get_bool(device, "optional", &what).unwrap_or(false);
+
+            Device { path, optional }
+        })
+        .collect();
+
+    let mut mounts: Vec<_> = get_entries(&config, "mounts", "src", &format!("Mounts for {} module", module))
+        .iter()
+        .map(|mount| {
+            let what = format!("Mount option for {} module", module);
+
+            let src = get_string(mount, "src", &what)
+                .unwrap_or_else(|| panic!("Mount for {} module is missing 'src'", module));
+
+            let dst = get_string(mount, "dst", &what)
+                .unwrap_or_else(|| src.clone());
+
+            let ro = get_bool(mount, "ro", &what).unwrap_or(false);
+            let optional = get_bool(mount, "optional", &what).unwrap_or(false);
+
+            Mount { src, dst, ro, optional }
+        })
+        .collect();
+
+    // Host paths are bind mounted to the same path inside the container
+    let host_paths = get_entries(&config, "host_paths", "path", &format!("Host paths for {} module", module));
+
+    for host_path in &host_paths {
+        let what = format!("Host path option for {} module", module);
+
+        let path = get_string(host_path, "path", &what)
+            .unwrap_or_else(|| panic!("Host path for {} module is missing 'path'", module));
+
+        let ro = get_bool(host_path, "ro", &what).unwrap_or(false);
+        let optional = get_bool(host_path, "optional", &what).unwrap_or(false);
+
+        mounts.push(Mount { src: path.clone(), dst: path, ro, optional });
+    }
+
+    let min_kernel = get_string(&config, "min_kernel", &format!("Minimum kernel version for {} module", module));
+    let max_kernel = get_string(&config, "max_kernel", &format!("Maximum kernel version for {} module", module));
+
+    let scripts = get_bool(&config, "scripts", &format!("Option for {} module", module)).unwrap_or(false);
+    let privileged = get_bool(&config, "privileged", &format!("Option for {} module", module)).unwrap_or(false);
+    let run_privileged = get_bool(&config, "run_privileged", &format!("Option for {} module", module)).unwrap_or(false);
+    let shell_privileged = get_bool(&config, "shell_privileged", &format!("Option for {} module", module)).unwrap_or(false);
+
+    let capabilities = get_string_array(
+        &config,
+        "capabilities",
+        &format!("Capabilities for {} module", module),
+    );
+
+    let run_capabilities = get_string_array(
+        &config,
+        "run_capabilities",
+        &format!("Capabilities for running commands in {} module", module),
+    );
+
+    let shell_capabilities = get_string_array(
+        &config,
+        "shell_capabilities",
+        &format!("Capabilities for shell sessions in {} module", module),
+    );
+
+    let autoload = get_bool(&config, "autoload", &format!("Option for {} module", module)).unwrap_or(false);
+    let build_on_load = get_bool(&config, "build_on_load", &format!("Option for {} module", module)).unwrap_or(false);
+
+    let depends = get_string_array(
+        &config,
+        "depends",
+        &format!("Module dependencies for {} module", module),
+    );
+
+    let before = get_string_array(
+        &config,
+        "before",
+        &format!("Units ordered after {} module", module),
+    );
+
+    let after = get_string_array(
+        &config,
+        "after",
+        &format!("Units ordered before {} module", module),
+    );
+
     ModuleConfig {
         name,
+        description,
         version,
         container_args,
+        load_args,
+        run_args,
+        shell_args,
+        unload_args,
         kernel_args,
         build_args,
+        env,
+        required_build_args,
+        objects,
+        binaries,
+        devices,
+        mounts,
+        min_kernel,
+        max_kernel,
+        scripts,
+        privileged,
+        run_privileged,
+        shell_privileged,
+        capabilities,
+        run_capabilities,
+        shell_capabilities,
+        autoload,
+        build_on_load,
+        depends,
+        before,
+        after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_param_appends_to_existing_table() {
+        let file = "data_dir = \"/usr/share/podmod\"\n\n[zfs.params]\nzfs_arc_min = 1\n\n[v4l2loopback]\nversion = \"0.12.7\"\n";
+        let edited = edit_param(file, "zfs", "zfs_arc_max", "4294967296").unwrap();
+
+        assert_eq!(
+            edited,
+            "data_dir = \"/usr/share/podmod\"\n\n[zfs.params]\nzfs_arc_min = 1\nzfs_arc_max = 4294967296\n\n[v4l2loopback]\nversion = \"0.12.7\"\n",
+        );
+    }
+
+    #[test]
+    fn edit_param_replaces_existing_value() {
+        let file = "[zfs.params]\n# Half of the memory\nzfs_arc_max = 1\n";
+        let edited = edit_param(file, "zfs", "zfs_arc_max", "2").unwrap();
+
+        assert_eq!(edited, "[zfs.params]\n# Half of the memory\nzfs_arc_max = 2\n");
+    }
+
+    #[test]
+    fn edit_param_creates_missing_table() {
+        let file = "data_dir = \"/usr/share/podmod\"\n";
+        let edited = edit_param(file, "zfs", "zfs_arc_max", "2").unwrap();
+
+        assert_eq!(edited, "data_dir = \"/usr/share/podmod\"\n\n[zfs.params]\nzfs_arc_max = 2\n");
+    }
+
+    #[test]
+    fn edit_param_finds_header_with_whitespace() {
+        let file = "[ zfs . \"params\" ]  # Tuning\nzfs_arc_max = 1\n";
+        let edited = edit_param(file, "zfs", "zfs_arc_max", "2").unwrap();
+
+        assert_eq!(edited, "[ zfs . \"params\" ]  # Tuning\nzfs_arc_max = 2\n");
+    }
+
+    #[test]
+    fn edit_param_quotes_strings() {
+        let file = "[v4l2loopback.params]\n";
+        let edited = edit_param(file, "v4l2loopback", "card_label", "Virtual Camera").unwrap();
+
+        assert_eq!(edited, "[v4l2loopback.params]\ncard_label = \"Virtual Camera\"\n");
+    }
+
+    #[test]
+    fn edit_param_writes_dotted_object_keys() {
+        let file = "[zfs.params]\nspl . spl_hostid = 1\n";
+        let edited = edit_param(file, "zfs", "spl.spl_hostid", "2").unwrap();
+
+        assert_eq!(edited, "[zfs.params]\nspl.spl_hostid = 2\n");
+    }
+
+    #[test]
+    fn edit_param_rejects_inline_table() {
+        let file = "[zfs]\nparams = { zfs_arc_max = 1 }\n";
+
+        assert!(edit_param(file, "zfs", "zfs_arc_max", "2").is_err());
+    }
+
+    #[test]
+    fn edit_param_rejects_dotted_table() {
+        let file = "[zfs]\nparams.zfs_arc_max = 1\n";
+
+        assert!(edit_param(file, "zfs", "zfs_arc_max", "2").is_err());
+    }
+
+    #[test]
+    fn edit_param_rejects_conflicting_object_table() {
+        let file = "[zfs.params]\nzfs_arc_max = 1\n\n[zfs.params.spl]\nspl_hostid = 1\n";
+
+        assert!(edit_param(file, "zfs", "spl.spl_hostid", "2").is_err());
+    }
+
+    #[test]
+    fn edit_param_rejects_invalid_file() {
+        assert!(edit_param("[zfs.params\n", "zfs", "zfs_arc_max", "2").is_err());
+    }
+
+    fn test_module(file: &str) -> ModuleConfig {
+        let config = Config {
+            data_dir: String::from("/nonexistent"),
+            cache_dir: String::from("/nonexistent"),
+            shim_dir: String::from("/nonexistent"),
+            tree: file.parse().unwrap(),
+        };
+
+        module(&config, "zfs")
+    }
+
+    #[test]
+    fn render_param_formats_booleans_like_sysfs() {
+        assert_eq!(render_param(&toml::Value::Boolean(true), "test"), "Y");
+        assert_eq!(render_param(&toml::Value::Boolean(false), "test"), "N");
+    }
+
+    #[test]
+    fn render_param_quotes_strings_with_whitespace() {
+        assert_eq!(render_param(&toml::Value::from("Virtual Camera"), "test"), "\"Virtual Camera\"");
+        assert_eq!(render_param(&toml::Value::from("camera"), "test"), "camera");
+    }
+
+    #[test]
+    fn render_param_joins_arrays_with_commas() {
+        let value = toml::Value::from(vec![toml::Value::from(1), toml::Value::from(2), toml::Value::from(3)]);
+
+        assert_eq!(render_param(&value, "test"), "1,2,3");
+    }
+
+    #[test]
+    #[should_panic]
+    fn render_param_rejects_nested_arrays() {
+        let value = toml::Value::from(vec![toml::Value::from(vec![toml::Value::from(1)])]);
+
+        render_param(&value, "test");
+    }
+
+    #[test]
+    fn object_kernel_args_applies_prefixed_args_to_object() {
+        let module = test_module("[zfs]\nversion = \"2.1.5\"\nobjects = [\"spl.ko\", \"zfs.ko\"]\nkernel_args = [\"spl.spl_hostid=1\", \"zfs_arc_max=2\"]\n");
+
+        assert_eq!(object_kernel_args(&module, "spl.ko"), vec!["spl_hostid=1"]);
+        assert_eq!(object_kernel_args(&module, "zfs.ko"), vec!["zfs_arc_max=2"]);
+    }
+
+    #[test]
+    fn object_kernel_args_ignores_dots_in_values() {
+        let module = test_module("[zfs]\nversion = \"2.1.5\"\nobjects = [\"spl.ko\", \"zfs.ko\"]\nkernel_args = [\"zfs_dbgmsg=spl.log\", \"spl.spl_hostid=a.b\"]\n");
+
+        assert_eq!(object_kernel_args(&module, "spl.ko"), vec!["spl_hostid=a.b"]);
+        assert_eq!(object_kernel_args(&module, "zfs.ko"), vec!["zfs_dbgmsg=spl.log"]);
+    }
+
+    #[test]
+    fn object_kernel_args_falls_back_to_primary_object() {
+        let module = test_module("[zfs]\nversion = \"2.1.5\"\nobjects = [\"spl.ko\", \"zfs.ko\"]\nkernel_args = [\"unknown.param=1\"]\n");
+
+        assert!(object_kernel_args(&module, "spl.ko").is_empty());
+        assert_eq!(object_kernel_args(&module, "zfs.ko"), vec!["unknown.param=1"]);
+    }
+
+    #[test]
+    fn split_param_key_splits_object_prefix() {
+        let module = test_module("[zfs]\nversion = \"2.1.5\"\nobjects = [\"spl/spl.ko\", \"zfs/zfs.ko\"]\n");
+
+        assert_eq!(split_param_key(&module, "spl.spl_hostid"), (String::from("spl"), String::from("spl_hostid")));
+        assert_eq!(split_param_key(&module, "zfs_arc_max"), (String::from("zfs"), String::from("zfs_arc_max")));
+        assert_eq!(split_param_key(&module, "unknown.param"), (String::from("zfs"), String::from("unknown.param")));
     }
 }
@@ -13,6 +13,10 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use nix::sys::stat;
+use nix::unistd;
+use std::collections;
+use std::fs;
 use std::process;
 use std::str;
 
@@ -48,27 +52,57 @@ pub fn kernel_version() -> String {
     String::from(output)
 }
 
-pub fn is_module_loaded(module: &str) -> bool {
-    // Call 'lsmod | grep $module' to check for loaded module
-    let lsmod = process::Command::new("lsmod")
-        .stdout(process::Stdio::piped())
-        .spawn()
-        .expect("Error while fetching loaded kernel modules");
+pub fn installed_kernels() -> Vec<String> {
+    // Kernels of the booted system, as well as kernels of pending
+    // rpm-ostree deployments that will be booted next
+    let mut dirs = vec![String::from("/usr/lib/modules")];
 
-    let mut grep = process::Command::new("grep")
-        .arg(module)
-        .stdin(process::Stdio::from(lsmod.stdout.unwrap()))
-        .stdout(process::Stdio::null())
-        .spawn()
-        .expect("Error while searching for loaded kernel module");
+    if let Ok(stateroots) = fs::read_dir("/ostree/deploy") {
+        for stateroot in stateroots.flatten() {
+            if let Ok(deployments) = fs::read_dir(stateroot.path().join("deploy")) {
+                for deployment in deployments.flatten() {
+                    dirs.push(format!("{}/usr/lib/modules", deployment.path().display()));
+                }
+            }
+        }
+    }
 
-    // 'grep' succeeds only if string is found
-    grep.wait().unwrap().success()
+    let mut kernels = collections::BTreeSet::new();
+
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            // Removed kernels may leave their directory behind, e.g. with
+            // objects installed by other tools, but never the kernel image
+            if entry.path().join("vmlinuz").is_file() {
+                kernels.insert(String::from(entry.file_name().to_str().unwrap()));
+            }
+        }
+    }
+
+    kernels.into_iter().collect()
+}
+
+pub fn loaded_modules() -> collections::HashSet<String> {
+    // Read '/proc/modules' to fetch the names of all loaded modules
+    // The name is always the first field of each line
+    let modules = fs::read_to_string("/proc/modules")
+        .expect("Error while fetching loaded kernel modules");
+
+    modules
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect()
 }
 
 pub fn is_secure_boot_enabled() -> bool {
     // Call 'mokutil --sb-state | grep enabled' to check for Secure Boot state
-    let mokutil = process::Command::new("mokutil")
+    let mut mokutil = process::Command::new("mokutil")
         .arg("--sb-state")
         .stdout(process::Stdio::piped())
         .spawn()
@@ -76,11 +110,30 @@ pub fn is_secure_boot_enabled() -> bool {
 
     let mut grep = process::Command::new("grep")
         .arg("enabled")
-        .stdin(process::Stdio::from(mokutil.stdout.unwrap()))
+        .stdin(process::Stdio::from(mokutil.stdout.take().unwrap()))
         .stdout(process::Stdio::null())
         .spawn()
         .expect("Error while determining Secure Boot state");
 
+    mokutil.wait().unwrap();
+
     // 'grep' succeeds only if string is found
     grep.wait().unwrap().success()
 }
+
+pub fn is_terminal(fd: i32) -> bool {
+    unistd::isatty(fd).unwrap_or(false)
+}
+
+pub fn is_stdin_readable() -> bool {
+    // Pipes, sockets, and redirected files carry input, unlike e.g. the
+    // '/dev/null' that systemd connects to a service's stdin by default
+    let mode = match stat::fstat(0) {
+        Ok(st) => stat::SFlag::from_bits_truncate(st.st_mode),
+        Err(_) => return false,
+    };
+
+    let kind = mode & stat::SFlag::S_IFMT;
+
+    is_terminal(0) || kind == stat::SFlag::S_IFIFO || kind == stat::SFlag::S_IFREG || kind == stat::SFlag::S_IFSOCK
+}
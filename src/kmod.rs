@@ -0,0 +1,50 @@
+/*
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use nix::kmod;
+use std::ffi;
+use std::fs;
+use std::io;
+
+pub fn insert(path: &str, params: &[String]) -> io::Result<()> {
+    let file = fs::File::open(path)?;
+
+    // Parameters are passed as a single space-separated string,
+    // just like on the command line of 'insmod'
+    let params = ffi::CString::new(params.join(" ")).unwrap();
+
+    // Equivalent to 'insmod'
+    kmod::finit_module(&file, &params, kmod::ModuleInitFlags::empty())?;
+
+    Ok(())
+}
+
+pub fn remove(name: &str, force: bool) -> io::Result<()> {
+    let name = ffi::CString::new(name).unwrap();
+
+    // Equivalent to 'rmmod', which doesn't wait for the module to
+    // become unused but fails immediately instead
+    let mut flags = kmod::DeleteModuleFlags::O_NONBLOCK;
+
+    // Equivalent to 'rmmod --force', which ignores the reference count
+    // This only works if the kernel supports forced unloading
+    if force {
+        flags |= kmod::DeleteModuleFlags::O_TRUNC;
+    }
+
+    kmod::delete_module(&name, flags)?;
+
+    Ok(())
+}
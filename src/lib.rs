@@ -13,13 +13,197 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::cmp;
+use std::collections;
+use std::env;
 use std::fs;
+use std::io;
+use std::os;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::panic;
 use std::path;
 use std::process;
 use std::str;
 
 pub mod config;
 mod fetch;
+mod kmod;
+pub mod lock;
+mod modinfo;
+mod notify;
+mod sysfs;
+
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pub tty: Option<bool>,
+    pub interactive: bool,
+    pub env: Vec<String>,
+    pub env_files: Vec<String>,
+    pub workdir: Option<String>,
+    pub mount_cwd: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum LoadState {
+    Loaded,
+    PartiallyLoaded { loaded: Vec<String>, missing: Vec<String> },
+    NotLoaded,
+}
+
+fn load_state(module: &config::ModuleConfig) -> LoadState {
+    let loaded_modules = fetch::loaded_modules();
+
+    // Sort the module's kernel objects by whether they are resident
+    let (loaded, missing): (Vec<_>, Vec<_>) = module.objects
+        .iter()
+        .map(|object| config::object_name(object))
+        .partition(|name| loaded_modules.contains(name));
+
+    if missing.is_empty() {
+        LoadState::Loaded
+    } else if loaded.is_empty() {
+        LoadState::NotLoaded
+    } else {
+        LoadState::PartiallyLoaded { loaded, missing }
+    }
+}
+
+fn rollback(module: &config::ModuleConfig, previously_loaded: &collections::HashSet<String>) -> (Vec<String>, Vec<String>) {
+    let loaded_modules = fetch::loaded_modules();
+
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+
+    // Remove every kernel object that became resident since, in reverse load order
+    for object in module.objects.iter().rev() {
+        let name = config::object_name(object);
+
+        if !loaded_modules.contains(&name) || previously_loaded.contains(&name) {
+            continue;
+        }
+
+        match kmod::remove(&name, false) {
+            Ok(()) => removed.push(name),
+            Err(error) => failed.push(format!("{} ({})", name, error)),
+        }
+    }
+
+    (removed, failed)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    // Levenshtein distance, computed one row at a time
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn validate_kernel_args(config: &config::Config, module: &config::ModuleConfig) {
+    if module.kernel_args.is_empty() {
+        return;
+    }
+
+    let kernel_version = fetch::kernel_version();
+
+    // Fetch the parameters exposed by each kernel object
+    let parameters: Vec<(String, Vec<String>)> = with_objects(config, module, &kernel_version, |dir| {
+        module.objects
+            .iter()
+            .map(|object| {
+                let info = modinfo::modinfo(&format!("{}/{}", dir, object));
+                let names = info.parameters.into_iter().map(|p| p.name).collect();
+
+                (String::from(object.as_str()), names)
+            })
+            .collect()
+    });
+
+    let mut errors = Vec::new();
+
+    for (object, names) in &parameters {
+        // The kernel treats '-' and '_' in parameter names alike
+        let normalized: Vec<_> = names.iter().map(|n| n.replace('-', "_")).collect();
+
+        for arg in config::object_kernel_args(module, object) {
+            let name = config::kernel_arg_name(&arg);
+            let normalized_name = name.replace('-', "_");
+
+            // 'dyndbg' and 'async_probe' are handled by the kernel for every module
+            if ["dyndbg", "async_probe"].contains(&normalized_name.as_str()) || normalized.contains(&normalized_name) {
+                continue;
+            }
+
+            let object = config::object_name(object);
+            let suggestion = names
+                .iter()
+                .zip(&normalized)
+                .map(|(n, normalized)| (edit_distance(&normalized_name, normalized), n))
+                .filter(|(distance, n)| *distance <= cmp::max(2, n.len() / 3))
+                .min();
+
+            match suggestion {
+                Some((_, suggestion)) => errors.push(format!(
+                    "unknown parameter '{}' for kernel object {} (did you mean '{}'?)",
+                    name, object, suggestion,
+                )),
+                None => errors.push(format!("unknown parameter '{}' for kernel object {}", name, object)),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        panic!("Invalid kernel parameters for {} module: {}", module.name, errors.join("; "));
+    }
+}
+
+fn module_users(module: &config::ModuleConfig) -> Vec<String> {
+    let loaded_modules = fetch::loaded_modules();
+
+    let names: Vec<_> = module.objects
+        .iter()
+        .map(|object| config::object_name(object))
+        .collect();
+
+    let mut users = Vec::new();
+
+    for name in names.iter().filter(|name| loaded_modules.contains(*name)) {
+        let holders = sysfs::holders(name);
+
+        // Kernel objects of the module itself are removed before the objects they depend on
+        let external: Vec<_> = holders
+            .iter()
+            .filter(|holder| !names.contains(holder))
+            .cloned()
+            .collect();
+
+        if !external.is_empty() {
+            users.push(format!("{} is used by {}", name, external.join(", ")));
+        }
+
+        // Each holding module accounts for one reference, anything beyond
+        // that is held by userspace, e.g. a mounted filesystem or an open device
+        let references = sysfs::refcount(name).saturating_sub(holders.len() as u32);
+
+        if references > 0 {
+            users.push(format!("{} has {} open references", name, references));
+        }
+    }
+
+    users
+}
 
 fn is_module_supported(data_dir: &str, module: &str) -> bool {
     // If the module is supported, it must have a subdirectory under 'data_dir'
@@ -27,6 +211,69 @@ fn is_module_supported(data_dir: &str, module: &str) -> bool {
     path::Path::new(&path).is_dir()
 }
 
+fn compare_kernel_versions(version: &str, bound: &str) -> cmp::Ordering {
+    // Only compare the numeric part of the kernel release (e.g. '6.0.7'
+    // from '6.0.7-301.fc37.x86_64') up to the precision of the bound,
+    // so that a bound of '6.0' matches every 6.0.x release
+    let components = |v: &str| -> Vec<u64> {
+        v.split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|c| c.parse().unwrap_or(0))
+            .collect()
+    };
+
+    let version = components(version);
+    let bound = components(bound);
+
+    for (i, b) in bound.iter().enumerate() {
+        let v = version.get(i).copied().unwrap_or(0);
+
+        match v.cmp(b) {
+            cmp::Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+
+    cmp::Ordering::Equal
+}
+
+fn is_kernel_supported(module: &config::ModuleConfig, kernel_version: &str) -> bool {
+    if let Some(min_kernel) = &module.min_kernel {
+        if compare_kernel_versions(kernel_version, min_kernel) == cmp::Ordering::Less {
+            return false;
+        }
+    }
+
+    if let Some(max_kernel) = &module.max_kernel {
+        if compare_kernel_versions(kernel_version, max_kernel) == cmp::Ordering::Greater {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn supported_modules(config: &config::Config) -> Vec<String> {
+    // Each supported module has a subdirectory in 'data_dir'
+    let modules = fs::read_dir(format!("{}/modules", config.data_dir))
+        .expect("Error while reading data directory");
+
+    let mut modules: Vec<_> = modules
+        .map(|module| {
+            // Use the path's basename
+            let module = module.unwrap().path();
+            let module = module.file_name().unwrap();
+
+            String::from(module.to_str().unwrap())
+        })
+        .collect();
+
+    modules.sort();
+    modules
+}
+
 fn get_build_image_identifier(kernel_version: &str) -> String {
     format!("{}-builder:{}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), kernel_version)
 }
@@ -49,7 +296,237 @@ fn image_exists(identifier: &str) -> bool {
         .success()
 }
 
-pub fn build(config: &config::Config, module: &config::ModuleConfig, idempotent: bool, no_prune: bool) {
+fn image_id(identifier: &str) -> Option<String> {
+    // Call 'podman image inspect' to fetch the ID of an existing image
+    let output = process::Command::new("podman")
+        .args(["image", "inspect", "--format", "{{.Id}}", identifier])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from(str::from_utf8(&output.stdout).unwrap().trim()))
+}
+
+fn get_cache_dir(config: &config::Config, module: &config::ModuleConfig, kernel_version: &str) -> String {
+    format!("{}/{}/{}", config.cache_dir, kernel_version, module.name)
+}
+
+fn is_cached(config: &config::Config, module: &config::ModuleConfig, kernel_version: &str) -> bool {
+    // The cache is only valid for the module version it was extracted from
+    let cache_dir = get_cache_dir(config, module, kernel_version);
+    let version = fs::read_to_string(format!("{}/VERSION", cache_dir));
+
+    if !matches!(version, Ok(version) if version.trim() == module.version) {
+        return false;
+    }
+
+    // The list of objects might have been changed since extracting them
+    let complete = module.objects
+        .iter()
+        .all(|object| path::Path::new(&format!("{}/{}", cache_dir, object)).is_file());
+
+    if !complete {
+        return false;
+    }
+
+    // Objects are only stamped with the image ID once they are complete
+    path::Path::new(&format!("{}/IMAGE", cache_dir)).is_file()
+}
+
+fn is_cache_current(config: &config::Config, module: &config::ModuleConfig, kernel_version: &str) -> bool {
+    if !is_cached(config, module, kernel_version) {
+        return false;
+    }
+
+    // If the image was rebuilt since, the cached objects are outdated
+    // Without the image, e.g. after pruning, the cache is all that is left
+    let cache_dir = get_cache_dir(config, module, kernel_version);
+    let image_name = get_module_image_identifier(&module.name, &module.version, kernel_version);
+
+    match image_id(&image_name) {
+        Some(id) => matches!(fs::read_to_string(format!("{}/IMAGE", cache_dir)), Ok(cached) if cached.trim() == id),
+        None => true,
+    }
+}
+
+fn is_kernel_cache(path: &path::Path) -> bool {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    // Every entry must be a module cache stamped by 'extract_objects'
+    entries.flatten().all(|entry| {
+        let path = entry.path();
+
+        path.is_dir() && path.join("VERSION").is_file() && path.join("IMAGE").is_file()
+    })
+}
+
+fn prune_cache(config: &config::Config) {
+    let kernel_versions = fetch::installed_kernels();
+
+    // Don't remove anything if the installed kernels can't be determined
+    if kernel_versions.is_empty() {
+        return;
+    }
+
+    let entries = match fs::read_dir(&config.cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let running_kernel_version = fetch::kernel_version();
+
+    for entry in entries.flatten() {
+        let name = String::from(entry.file_name().to_str().unwrap());
+
+        // Each kernel version has its own subdirectory
+        if !entry.path().is_dir() || name == running_kernel_version || kernel_versions.contains(&name) {
+            continue;
+        }
+
+        // Never touch anything that podmod didn't put there itself
+        if !is_kernel_cache(&entry.path()) {
+            continue;
+        }
+
+        println!("Removing cached kernel objects for kernel version {} ...", name);
+
+        fs::remove_dir_all(entry.path()).expect("Error while removing outdated cache directory");
+    }
+}
+
+fn copy_file(src: &str, dst: &str) -> io::Result<()> {
+    let dst = path::Path::new(dst);
+
+    fs::create_dir_all(dst.parent().unwrap())?;
+    fs::copy(src, dst)?;
+
+    Ok(())
+}
+
+fn copy_objects(module: &config::ModuleConfig, extra_dir: &str, dst_dir: &str) -> io::Result<()> {
+    // Keep the directory layout below 'extra/'
+    module.objects
+        .iter()
+        .try_for_each(|object| copy_file(&format!("{}/{}", extra_dir, object), &format!("{}/{}", dst_dir, object)))
+}
+
+fn extract_objects(config: &config::Config, module: &config::ModuleConfig, kernel_version: &str) {
+    // podmod's container images are always named predictably
+    let image_name = get_module_image_identifier(&module.name, &module.version, kernel_version);
+
+    // Ensure module is built
+    if !image_exists(&image_name) {
+        panic!("Module {} is not built", module.name);
+    }
+
+    println!("Extracting kernel objects of module {} ...", module.name);
+
+    let cache_dir = get_cache_dir(config, module, kernel_version);
+    let staging_dir = format!("{}.tmp", cache_dir);
+
+    // Start from a clean staging directory, in case a previous extraction was interrupted
+    if path::Path::new(&staging_dir).exists() {
+        fs::remove_dir_all(&staging_dir).expect("Error while removing stale cache directory");
+    }
+
+    // Access the kernel objects inside the image from the host
+    let root = mount_image(&image_name);
+    let extra_dir = format!("{}/usr/lib/modules/{}/extra", root, kernel_version);
+
+    let result = copy_objects(module, &extra_dir, &staging_dir);
+
+    unmount_image(&image_name);
+    result.unwrap_or_else(|e| panic!("Error while extracting kernel objects of module {} ({})", module.name, e));
+
+    fs::write(format!("{}/VERSION", staging_dir), &module.version)
+        .expect("Error while writing cache version");
+
+    let id = image_id(&image_name).expect("Error while fetching image ID");
+
+    fs::write(format!("{}/IMAGE", staging_dir), id)
+        .expect("Error while writing cache image ID");
+
+    // Replace the previous cache in one step, so that it is never seen half-written
+    if path::Path::new(&cache_dir).exists() {
+        fs::remove_dir_all(&cache_dir).expect("Error while removing outdated cache directory");
+    }
+
+    fs::rename(&staging_dir, &cache_dir).expect("Error while populating cache directory");
+}
+
+fn with_objects<T>(config: &config::Config, module: &config::ModuleConfig, kernel_version: &str, f: impl FnOnce(&str) -> T) -> T {
+    // Prefer the extracted kernel objects, so that Podman isn't needed
+    if is_cached(config, module, kernel_version) {
+        return f(&get_cache_dir(config, module, kernel_version));
+    }
+
+    // podmod's container images are always named predictably
+    let image_name = get_module_image_identifier(&module.name, &module.version, kernel_version);
+
+    // Ensure module is built
+    if !image_exists(&image_name) {
+        panic!("Module {} is not built", module.name);
+    }
+
+    // Access the kernel objects inside the image from the host
+    let root = mount_image(&image_name);
+    let result = f(&format!("{}/usr/lib/modules/{}/extra", root, kernel_version));
+
+    unmount_image(&image_name);
+    result
+}
+
+fn mount_image(identifier: &str) -> String {
+    // Call 'podman image mount' to access the image's filesystem from the host
+    // The command prints the path of the mount point
+    let output = process::Command::new("podman")
+        .args(["image", "mount", identifier])
+        .output()
+        .expect("Error while mounting image");
+
+    if !output.status.success() {
+        panic!("Error while mounting image {}", identifier);
+    }
+
+    let path = str::from_utf8(&output.stdout).unwrap();
+    let path = str::trim(path);
+
+    String::from(path)
+}
+
+fn unmount_image(identifier: &str) {
+    process::Command::new("podman")
+        .args(["image", "unmount", identifier])
+        .stdout(process::Stdio::null())
+        .status()
+        .expect("Error while unmounting image");
+}
+
+fn prune() {
+    // Pruning may remove images that other podmod processes are using,
+    // so it is skipped instead of waiting for them
+    let pruned = lock::with_exclusive_global(|| {
+        notify::status("Pruning intermediary images");
+
+        process::Command::new("podman")
+            .args(["system", "prune", "-f"])
+            .status()
+            .expect("Error while pruning intermediary images");
+    });
+
+    if !pruned {
+        println!("Skipping pruning of intermediary images while another podmod process is running");
+    }
+}
+
+pub fn build(config: &config::Config, module: &config::ModuleConfig, kernel_version: Option<&str>, idempotent: bool, no_prune: bool) {
     // Ensure module is supported
     if !is_module_supported(&config.data_dir, &module.name) {
         panic!("Module {} is not supported", module.name);
@@ -57,7 +534,11 @@ pub fn build(config: &config::Config, module: &config::ModuleConfig, idempotent:
 
     // We'll need some information about the system when
     // compiling the kernel module
-    let kernel_version = fetch::kernel_version();
+    let kernel_version = match kernel_version {
+        Some(kernel_version) => String::from(kernel_version),
+        None => fetch::kernel_version(),
+    };
+
     let arch = fetch::architecture();
     let podmod_version = env!("CARGO_PKG_VERSION");
 
@@ -65,44 +546,78 @@ pub fn build(config: &config::Config, module: &config::ModuleConfig, idempotent:
     let runtime_image_name = get_runtime_image_identifier(&kernel_version);
     let module_image_name = get_module_image_identifier(&module.name, &module.version, &kernel_version);
 
+    // Ensure the module can be built against the target kernel
+    if !is_kernel_supported(module, &kernel_version) {
+        panic!("Module {} does not support kernel version {}", module.name, kernel_version);
+    }
+
+    // Ensure all build arguments required by the module are set
+    for arg in &module.required_build_args {
+        if !module.build_args.contains_key(arg) {
+            panic!("Missing build argument {} for {} module", arg, module.name);
+        }
+    }
+
     // Check for existing image
     if image_exists(&module_image_name) {
         if idempotent {
+            // The image might have been rebuilt without refreshing the cache
+            if !module.scripts && !is_cache_current(config, module, &kernel_version) {
+                extract_objects(config, module, &kernel_version);
+            }
+
             return;
         }
 
         panic!("Module {} is already built", module.name);
     }
 
-    // Build builder image
-    if !image_exists(&build_image_name) {
-        println!("Building builder image for kernel version {} ...", kernel_version);
+    // The builder and runtime images are shared by all modules, so make
+    // concurrent builds for the same kernel wait for each other
+    {
+        let _lock = lock::exclusive(&format!("builder-{}", kernel_version));
 
-        process::Command::new("podman")
-            .args(["build", "-t", &build_image_name])
-            .args(["--build-arg", &format!("ARCH={}", arch)])
-            .args(["--build-arg", &format!("KERNEL_VERSION={}", kernel_version)])
-            .args(["--file", "Builder.containerfile"])
-            .arg(format!("{}/common/", config.data_dir))
-            .status()
-            .expect("Error while building the builder image");
-    }
+        // Build builder image
+        if !image_exists(&build_image_name) {
+            println!("Building builder image for kernel version {} ...", kernel_version);
+            notify::status(&format!("Building builder image for kernel version {}", kernel_version));
 
-    // Build runtime image
-    if !image_exists(&runtime_image_name) {
-        println!("Building runtime image for kernel version {} ...", kernel_version);
+            let status = process::Command::new("podman")
+                .args(["build", "-t", &build_image_name])
+                .args(["--build-arg", &format!("ARCH={}", arch)])
+                .args(["--build-arg", &format!("KERNEL_VERSION={}", kernel_version)])
+                .args(["--file", "Builder.containerfile"])
+                .arg(format!("{}/common/", config.data_dir))
+                .status()
+                .expect("Error while building the builder image");
 
-        process::Command::new("podman")
-            .args(["build", "-t", &runtime_image_name])
-            .args(["--build-arg", &format!("KERNEL_VERSION={}", kernel_version)])
-            .args(["--build-arg", &format!("PODMOD_VERSION={}", podmod_version)])
-            .args(["--file", "Runtime.containerfile"])
-            .arg(format!("{}/common/", config.data_dir))
-            .status()
-            .expect("Error while building the runtime image");
+            if !status.success() {
+                panic!("Error while building builder image for kernel version {}", kernel_version);
+            }
+        }
+
+        // Build runtime image
+        if !image_exists(&runtime_image_name) {
+            println!("Building runtime image for kernel version {} ...", kernel_version);
+            notify::status(&format!("Building runtime image for kernel version {}", kernel_version));
+
+            let status = process::Command::new("podman")
+                .args(["build", "-t", &runtime_image_name])
+                .args(["--build-arg", &format!("KERNEL_VERSION={}", kernel_version)])
+                .args(["--build-arg", &format!("PODMOD_VERSION={}", podmod_version)])
+                .args(["--file", "Runtime.containerfile"])
+                .arg(format!("{}/common/", config.data_dir))
+                .status()
+                .expect("Error while building the runtime image");
+
+            if !status.success() {
+                panic!("Error while building runtime image for kernel version {}", kernel_version);
+            }
+        }
     }
 
     println!("Building module {} for kernel version {} ...", module.name, kernel_version);
+    notify::status(&format!("Building module {} for kernel version {}", module.name, kernel_version));
 
     // Build the new image
     // We already know the target architecture and kernel version
@@ -120,121 +635,1057 @@ pub fn build(config: &config::Config, module: &config::ModuleConfig, idempotent:
         command.args(["--build-arg", &format!("{}={}", key, value)]);
     }
 
-    command
+    let status = command
         .arg(format!("{}/modules/{}", &config.data_dir, module.name))
         .status()
         .expect("Error while building the kernel module");
 
+    if !status.success() {
+        panic!("Error while building module {} for kernel version {}", module.name, kernel_version);
+    }
+
+    // Extract the kernel objects right away, so that loading
+    // the module doesn't need to start Podman
+    if !module.scripts {
+        extract_objects(config, module, &kernel_version);
+    }
+
+    // Kernel objects for removed kernels are of no use anymore
+    prune_cache(config);
+
     // By default, we'll prune any intermediary images that the build generates
     // The user probably isn't building the same image multiple times,
     // so keeping the cached build stages isn't very useful
     if !no_prune {
-        process::Command::new("podman")
-            .args(["system", "prune", "-f"])
-            .status()
-            .expect("Error while pruning intermediary images");
+        prune();
     }
 }
 
-pub fn load(module: &config::ModuleConfig, idempotent: bool) {
-    // Check if module is already loaded
-    if fetch::is_module_loaded(&module.name) {
-        if idempotent {
-            return;
-        }
-
-        panic!("Module {} is already loaded", module.name);
-    }
-
-    // Check if Secure Boot is enabled
-    if fetch::is_secure_boot_enabled() {
-        panic!("Cannot load unsigned kernel modules if Secure Boot is enabled");
-    }
-
-    println!("Loading module {} ...", module.name);
-
-    // Don't pass container_args when loading the module
-    // Missing bind mount targets, etc. might not exist yet
-    let mut module = module.clone();
-    module.container_args = Vec::new();
-
-    let mut command = vec![String::from("load")];
-    command.extend(module.kernel_args.clone());
-
-    // Call the load script inside a new container
-    // Add additional kernel parameters passed to the function
-    run(&module, &command);
-}
+pub fn export(config: &config::Config, module: &config::ModuleConfig, kernel_version: Option<&str>, output: &str, userland: bool) {
+    // Export the module built for the running kernel by default
+    let kernel_version = match kernel_version {
+        Some(kernel_version) => String::from(kernel_version),
+        None => fetch::kernel_version(),
+    };
 
-pub fn modules(config: &config::Config) {
-    println!("The following kernel modules are supported:");
+    println!("Exporting module {} for kernel version {} to {} ...", module.name, kernel_version, output);
 
-    // Each supported module has a subdirectory in 'data_dir'
-    let modules = fs::read_dir(format!("{}/modules", config.data_dir))
-        .expect("Error while reading data directory");
+    // Kernel objects may also be exported from the cache, e.g. after the image was pruned
+    with_objects(config, module, &kernel_version, |dir| copy_objects(module, dir, &format!("{}/extra", output)))
+        .unwrap_or_else(|e| panic!("Error while exporting module {} ({})", module.name, e));
 
-    for module in modules {
-        // Print the path's basename
-        let module = module.unwrap().path();
-        let module = module.file_name().unwrap();
-        let module = module.to_str().unwrap();
-        println!("{}", module);
+    if !userland {
+        return;
     }
-}
 
-pub fn run(module: &config::ModuleConfig, command: &Vec<String>) {
     // podmod's container images are always named predictably
-    let kernel_version = fetch::kernel_version();
     let image_name = get_module_image_identifier(&module.name, &module.version, &kernel_version);
 
-    // Ensure module is built
+    // Userland binaries are only available inside the image
     if !image_exists(&image_name) {
-        panic!("Module {} is not built", module.name);
+        panic!("Module {} is not built for kernel version {}", module.name, kernel_version);
     }
 
-    println!("Executing command {:?}, in module {} ...", command, module.name);
+    // Access the files inside the image from the host
+    let root = mount_image(&image_name);
 
-    // Run the command inside a new container
-    // Add additional Podman arguments from module configuration to the function
-    process::Command::new("podman")
-        .args(["run", "--rm", "--privileged"])
-        .args(&module.container_args)
-        .arg(&image_name)
-        .args(command)
-        .status()
-        .expect("Error while loading the kernel module");
+    // Userland binaries are copied flat into 'bin/'
+    let result = module.binaries
+        .iter()
+        .try_for_each(|binary| {
+            let name = binary.rsplit('/').next().unwrap_or(binary);
+            copy_file(&format!("{}{}", root, binary), &format!("{}/bin/{}", output, name))
+        });
+
+    unmount_image(&image_name);
+    result.unwrap_or_else(|e| panic!("Error while exporting module {} ({})", module.name, e));
 }
 
-pub fn shell(module: &config::ModuleConfig, shell: &str) {
-    let mut module = module.clone();
-    module.container_args.extend(vec![String::from("-it")]);
+pub fn generate_units(config: &config::Config, dir: &str) {
+    // The template unit shipped with podmod
+    let unit_path = "/usr/lib/systemd/system/podmod@.service";
 
-    println!("Starting shell session in module {} ...", module.name);
+    // A broken module must not keep the other modules from being loaded at boot
+    // The panic message has already been printed when it is caught
+    let modules: Vec<_> = supported_modules(config)
+        .into_iter()
+        .filter_map(|name| match panic::catch_unwind(|| config::module(config, &name)) {
+            Ok(module) => Some(module),
+            Err(_) => {
+                eprintln!("Skipping module {} due to invalid configuration", name);
+                None
+            }
+        })
+        .collect();
 
-    // Call the load script inside a new container
-    // Add additional kernel parameters passed to the function
-    run(&module, &vec![String::from(shell)]);
-}
+    // Modules loaded before other units, e.g. zfs before 'zfs-mount.service', have to be
+    // loaded before 'sysinit.target', and so do the modules they depend on
+    let mut early: collections::HashSet<_> = modules
+        .iter()
+        .filter(|module| module.autoload && !module.before.is_empty())
+        .map(|module| module.name.clone())
+        .collect();
 
-pub fn unload(module: &config::ModuleConfig, idempotent: bool) {
-    // Check if module is loaded
-    if !fetch::is_module_loaded(&module.name) {
-        if idempotent {
-            return;
+    loop {
+        let dependencies: Vec<_> = modules
+            .iter()
+            .filter(|module| early.contains(&module.name))
+            .flat_map(|module| module.depends.iter().cloned())
+            .filter(|dependency| !early.contains(dependency))
+            .collect();
+
+        if dependencies.is_empty() {
+            break;
         }
 
-        panic!("Module {} is not loaded", module.name);
+        early.extend(dependencies);
     }
 
-    // podmod's container images are always named predictably
-    let kernel_version = fetch::kernel_version();
-    let image_name = get_module_image_identifier(&module.name, &module.version, &kernel_version);
+    // Dependencies are pulled in by the modules requiring them, but still need
+    // the same ordering if they are loaded early
+    for module in modules.iter().filter(|module| module.autoload || early.contains(&module.name)) {
+        let unit = format!("podmod@{}.service", module.name);
+        let is_early = early.contains(&module.name);
 
-    println!("Unloading module {} ...", module.name);
+        // Enable the unit for this boot, just like 'systemctl enable' would
+        if module.autoload {
+            let target = if is_early { "sysinit.target" } else { "multi-user.target" };
+            let wants_dir = format!("{}/{}.wants", dir, target);
+            fs::create_dir_all(&wants_dir).expect("Error while creating unit directory");
 
-    // Call the unload script inside a new container
-    process::Command::new("podman")
-        .args(["run", "--rm", "--privileged", &image_name, "unload"])
-        .status()
-        .expect("Error while unloading the kernel module");
+            let link = format!("{}/{}", wants_dir, unit);
+
+            if fs::symlink_metadata(&link).is_err() {
+                os::unix::fs::symlink(unit_path, &link).expect("Error while enabling unit");
+            }
+        }
+
+        // Derive the unit ordering from the module configuration
+        let mut lines = vec![String::from("[Unit]")];
+
+        if is_early {
+            // The default dependencies would order the unit after 'sysinit.target'
+            // Loading from the cache only needs the cache and data directories
+            lines.push(String::from("DefaultDependencies=no"));
+            lines.push(String::from("Conflicts=shutdown.target"));
+            lines.push(String::from("Before=sysinit.target shutdown.target"));
+            lines.push(format!("RequiresMountsFor={} {}", config.cache_dir, config.data_dir));
+        }
+
+        for dependency in &module.depends {
+            lines.push(format!("Requires=podmod@{}.service", dependency));
+            lines.push(format!("After=podmod@{}.service", dependency));
+        }
+
+        lines.extend(module.before.iter().map(|unit| format!("Before={}", unit)));
+
+        for unit in &module.after {
+            // Most units are ordered after 'sysinit.target', which would be an ordering cycle
+            if is_early {
+                eprintln!("Ignoring {} in after for module {}, since it is loaded before sysinit.target", unit, module.name);
+                continue;
+            }
+
+            lines.push(format!("After={}", unit));
+        }
+
+        if lines.len() == 1 {
+            continue;
+        }
+
+        let dropin_dir = format!("{}/{}.d", dir, unit);
+        fs::create_dir_all(&dropin_dir).expect("Error while creating unit directory");
+
+        let dropin = format!("# Automatically generated by podmod generate-units\n\n{}\n", lines.join("\n"));
+
+        fs::write(format!("{}/50-ordering.conf", dropin_dir), dropin)
+            .expect("Error while writing unit drop-in");
+    }
+}
+
+pub fn info(config: &config::Config, module: &config::ModuleConfig, json: bool) {
+    let kernel_version = fetch::kernel_version();
+
+    // Read the module information embedded in each kernel object
+    let infos: Vec<_> = with_objects(config, module, &kernel_version, |dir| {
+        module.objects
+            .iter()
+            .map(|object| {
+                let mut info = modinfo::modinfo(&format!("{}/{}", dir, object));
+
+                // Older versions of 'modinfo' don't print the name
+                if info.name.is_empty() {
+                    info.name = config::object_name(object);
+                }
+
+                info
+            })
+            .collect()
+    });
+
+    if json {
+        let infos: Vec<_> = infos.iter().map(modinfo::to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&infos).unwrap());
+        return;
+    }
+
+    for info in &infos {
+        println!("{}", info.name);
+        println!("  version:  {}", info.version);
+        println!("  vermagic: {}", info.vermagic);
+        println!("  license:  {}", info.license);
+        println!("  signer:   {}", if info.signer.is_empty() { "(unsigned)" } else { &info.signer });
+        println!("  depends:  {}", info.depends.join(", "));
+
+        if info.parameters.is_empty() {
+            continue;
+        }
+
+        println!("  parameters:");
+
+        for parameter in &info.parameters {
+            println!("    {} ({})", parameter.name, parameter.kind);
+
+            if !parameter.description.is_empty() {
+                println!("        {}", parameter.description);
+            }
+        }
+    }
+}
+
+pub fn load(config: &config::Config, module: &config::ModuleConfig, idempotent: bool, build_missing: bool) {
+    // Check if module is already loaded
+    match load_state(module) {
+        LoadState::Loaded => {
+            if idempotent {
+                notify::ready();
+                return;
+            }
+
+            panic!("Module {} is already loaded", module.name);
+        }
+        LoadState::PartiallyLoaded { loaded, missing } => {
+            // The load script would fail on the first resident object
+            panic!(
+                "Module {} is partially loaded (loaded: {}; missing: {}), unload it first",
+                module.name,
+                loaded.join(", "),
+                missing.join(", "),
+            );
+        }
+        LoadState::NotLoaded => {}
+    }
+
+    // Check if Secure Boot is enabled
+    if fetch::is_secure_boot_enabled() {
+        panic!("Cannot load unsigned kernel modules if Secure Boot is enabled");
+    }
+
+    // Build the module for the running kernel if needed, e.g. after a kernel update
+    if build_missing || module.build_on_load {
+        let kernel_version = fetch::kernel_version();
+        let image_name = get_module_image_identifier(&module.name, &module.version, &kernel_version);
+
+        let needs_image = module.scripts || !is_cached(config, module, &kernel_version);
+
+        if needs_image && !image_exists(&image_name) {
+            build(config, module, None, true, false);
+        }
+    }
+
+    // Catch typos in kernel parameters before anything is loaded
+    validate_kernel_args(config, module);
+
+    println!("Loading module {} ...", module.name);
+    notify::status(&format!("Loading module {}", module.name));
+
+    // Remember which kernel objects were resident beforehand, so that
+    // a failed load can be rolled back without touching them
+    let previously_loaded = fetch::loaded_modules();
+
+    let result = if module.scripts {
+        load_with_script(module)
+    } else {
+        load_natively(config, module)
+    };
+
+    let cause = match result {
+        Ok(()) => {
+            notify::status(&format!("Loaded module {}", module.name));
+            notify::ready();
+            return;
+        }
+        Err(cause) => cause,
+    };
+
+    // Leave the host as it was before loading started
+    let (removed, failed) = rollback(module, &previously_loaded);
+
+    if !removed.is_empty() {
+        println!("Rolled back kernel objects {} ...", removed.join(", "));
+    }
+
+    if !failed.is_empty() {
+        panic!(
+            "Error while loading module {}: {}, and kernel objects {} could not be rolled back",
+            module.name,
+            cause,
+            failed.join(", "),
+        );
+    }
+
+    panic!("Error while loading module {}: {}", module.name, cause);
+}
+
+fn load_natively(config: &config::Config, module: &config::ModuleConfig) -> Result<(), String> {
+    let kernel_version = fetch::kernel_version();
+    let cache_dir = get_cache_dir(config, module, &kernel_version);
+
+    // Loading from the cache doesn't require Podman at all
+    // Only fall back to the module image if it hasn't been extracted yet
+    if !is_cached(config, module, &kernel_version) {
+        extract_objects(config, module, &kernel_version);
+    }
+
+    // Insert each kernel object in the declared order
+    module.objects
+        .iter()
+        .try_for_each(|object| {
+            let path = format!("{}/{}", cache_dir, object);
+            let args = config::object_kernel_args(module, object);
+
+            kmod::insert(&path, &args)
+                .map_err(|e| format!("inserting kernel object {} failed ({})", config::object_name(object), e))
+        })
+}
+
+fn load_with_script(module: &config::ModuleConfig) -> Result<(), String> {
+    let mut command = vec![String::from("load")];
+    command.extend(module.kernel_args.clone());
+
+    eprintln!("Executing command {:?}, in module {} ...", command, module.name);
+
+    // Call the load script inside a new container
+    // Add additional kernel parameters passed to the function
+    let status = run_container(module, config::Operation::Load, &[], &command);
+
+    if !status.success() {
+        return Err(format!("load script failed with {}", status));
+    }
+
+    Ok(())
+}
+
+pub fn modules(config: &config::Config) {
+    println!("The following kernel modules are supported:");
+
+    for module in &supported_modules(config) {
+        // Print the description from the module's manifest, if any
+        let description = config::manifest(&config.data_dir, module)
+            .and_then(|manifest| manifest.get("description").cloned())
+            .and_then(|description| description.as_str().map(String::from));
+
+        match description {
+            Some(description) => println!("{:<16} {}", module, description),
+            None => println!("{}", module),
+        }
+    }
+}
+
+pub fn params(module: &config::ModuleConfig) {
+    let loaded_modules = fetch::loaded_modules();
+    let primary = module.objects.last().map(|o| config::object_name(o));
+
+    for object in &module.objects {
+        let name = config::object_name(object);
+
+        if !loaded_modules.contains(&name) {
+            println!("{} (not loaded)", name);
+            continue;
+        }
+
+        println!("{}", name);
+
+        // Parameters of kernel objects other than the last one are prefixed
+        // with the object name, the same way as in 'kernel_args'
+        let prefix = if primary.as_deref() == Some(name.as_str()) {
+            String::new()
+        } else {
+            format!("{}.", name)
+        };
+
+        for parameter in sysfs::parameters(&name) {
+            let value = parameter.value.as_deref().unwrap_or("(unreadable)");
+            let access = if parameter.writable { "" } else { " (read-only)" };
+
+            println!("  {}{} = {}{}", prefix, parameter.name, value, access);
+        }
+    }
+}
+
+pub fn set_params(config_path: &str, module: &config::ModuleConfig, assignments: &[String], persist: bool) {
+    let loaded_modules = fetch::loaded_modules();
+
+    // Check every assignment before changing anything
+    let assignments: Vec<_> = assignments
+        .iter()
+        .map(|assignment| {
+            let (key, value) = assignment
+                .split_once('=')
+                .unwrap_or_else(|| panic!("Parameter assignment '{}' must be formatted as KEY=VALUE", assignment));
+
+            let (object, name) = config::split_param_key(module, key);
+
+            if !loaded_modules.contains(&object) {
+                panic!("Kernel object {} of module {} is not loaded", object, module.name);
+            }
+
+            let parameter = sysfs::parameters(&object)
+                .into_iter()
+                .find(|p| p.name == name)
+                .unwrap_or_else(|| panic!("Kernel object {} has no parameter {}", object, name));
+
+            if !parameter.writable {
+                panic!("Parameter {} of kernel object {} is read-only", name, object);
+            }
+
+            (key, value, object, name)
+        })
+        .collect();
+
+    // Prepare the configuration file, so that it can't fail after the runtime values were changed
+    // The values are also applied the next time the module is loaded
+    let file = if persist {
+        let edits: Vec<_> = assignments.iter().map(|(key, value, _, _)| (*key, *value)).collect();
+        Some(config::edit_params(config_path, &module.name, &edits))
+    } else {
+        None
+    };
+
+    for (_, value, object, name) in &assignments {
+        println!("Setting parameter {} of kernel object {} to {} ...", name, object, value);
+
+        sysfs::set_parameter(object, name, value)
+            .unwrap_or_else(|e| panic!("Error while setting parameter {} of kernel object {} ({})", name, object, e));
+    }
+
+    if let Some(file) = file {
+        fs::write(config_path, file)
+            .unwrap_or_else(|_| panic!("Error while writing configuration file at {}", config_path));
+    }
+}
+
+pub fn reload(config: &config::Config, module: &config::ModuleConfig, preserve_params: bool) {
+    let loaded_modules = fetch::loaded_modules();
+
+    // Remember the current values of all writable parameters
+    let snapshot: Vec<_> = if preserve_params {
+        module.objects
+            .iter()
+            .map(|object| config::object_name(object))
+            .filter(|name| loaded_modules.contains(name))
+            .flat_map(|name| {
+                sysfs::parameters(&name)
+                    .into_iter()
+                    .filter(|p| p.writable)
+                    .filter_map(move |p| p.value.map(|value| (name.clone(), p.name, value)))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    unload(module, true, false);
+    load(config, module, false, false);
+
+    let mut failed = Vec::new();
+
+    for (object, name, value) in snapshot {
+        let current = sysfs::parameters(&object).into_iter().find(|p| p.name == name);
+
+        match current {
+            // Nothing to do if the configured value is the same
+            Some(current) if current.value.as_deref() == Some(value.as_str()) => continue,
+            Some(current) if current.writable => {}
+            _ => {
+                failed.push(format!("{}.{} (no longer writable)", object, name));
+                continue;
+            }
+        }
+
+        if let Err(e) = sysfs::set_parameter(&object, &name, &value) {
+            failed.push(format!("{}.{} ({})", object, name, e));
+        }
+    }
+
+    if !failed.is_empty() {
+        eprintln!("Could not restore parameters {}", failed.join(", "));
+    }
+}
+
+fn expand_path(path: &str) -> Vec<String> {
+    // Support a trailing wildcard, e.g. '/dev/video*', for device nodes
+    // that are numbered dynamically
+    let prefix = match path.strip_suffix('*') {
+        Some(prefix) => prefix,
+        None if path::Path::new(path).exists() => return vec![String::from(path)],
+        None => return Vec::new(),
+    };
+
+    let (dir, name_prefix) = prefix.rsplit_once('/').unwrap_or((".", prefix));
+
+    let entries = match fs::read_dir(if dir.is_empty() { "/" } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(String::from))
+        .filter(|name| name.starts_with(name_prefix))
+        .map(|name| format!("{}/{}", dir, name))
+        .collect();
+
+    paths.sort();
+    paths
+}
+
+fn device_args(module: &config::ModuleConfig, operation: config::Operation) -> Vec<String> {
+    let mut args = Vec::new();
+
+    for device in &module.devices {
+        let paths = expand_path(&device.path);
+
+        // Device nodes are usually created when the module is loaded, and are
+        // missing when unloading a partially loaded module
+        if paths.is_empty() && !device.optional && operation != config::Operation::Unload {
+            panic!(
+                "Device {} required by module {} does not exist, make sure the module is loaded",
+                device.path, module.name,
+            );
+        }
+
+        args.extend(paths.iter().map(|path| format!("--device={}", path)));
+    }
+
+    for mount in &module.mounts {
+        if !path::Path::new(&mount.src).exists() {
+            if mount.optional {
+                continue;
+            }
+
+            panic!("Mount source {} required by module {} does not exist", mount.src, module.name);
+        }
+
+        let mut arg = format!("--mount=type=bind,src={},dst={}", mount.src, mount.dst);
+
+        if mount.ro {
+            arg.push_str(",ro=true");
+        }
+
+        args.push(arg);
+    }
+
+    args
+}
+
+fn mount_destinations(args: &[String]) -> Vec<String> {
+    let mut destinations = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        // Both '--mount=type=bind,src=...,dst=...' and '--volume SRC:DST[:OPTIONS]' are accepted
+        if let Some(options) = arg.strip_prefix("--mount=") {
+            let dst = options
+                .split(',')
+                .filter_map(|option| option.split_once('='))
+                .find(|(key, _)| ["dst", "destination", "target"].contains(key));
+
+            destinations.extend(dst.map(|(_, dst)| String::from(dst)));
+            continue;
+        }
+
+        let volume = match arg.as_str() {
+            "-v" | "--volume" => args.next().map(String::as_str),
+            arg => arg.strip_prefix("--volume=").or_else(|| arg.strip_prefix("-v=")),
+        };
+
+        if let Some(dst) = volume.and_then(|volume| volume.split(':').nth(1)) {
+            destinations.push(String::from(dst));
+        }
+    }
+
+    destinations
+}
+
+fn security_args(module: &config::ModuleConfig, operation: config::Operation) -> Vec<String> {
+    // Full access to the host has to be requested explicitly
+    if config::privileged(module, operation) {
+        return vec![String::from("--privileged")];
+    }
+
+    let mut args = Vec::new();
+
+    if let config::Operation::Load | config::Operation::Unload = operation {
+        // Only keep the capability needed to insert and remove kernel objects
+        // The default SELinux policy doesn't allow containers to load modules
+        args.push(String::from("--cap-drop=all"));
+        args.push(String::from("--security-opt=label=disable"));
+    }
+
+    for capability in config::capabilities(module, operation) {
+        args.push(format!("--cap-add={}", capability));
+    }
+
+    args
+}
+
+fn run_container(module: &config::ModuleConfig, operation: config::Operation, extra_args: &[String], command: &[String]) -> process::ExitStatus {
+    // podmod's container images are always named predictably
+    let kernel_version = fetch::kernel_version();
+    let image_name = get_module_image_identifier(&module.name, &module.version, &kernel_version);
+
+    // Ensure module is built
+    if !image_exists(&image_name) {
+        panic!("Module {} is not built", module.name);
+    }
+
+    let mut args = security_args(module, operation);
+    args.extend(extra_args.iter().cloned());
+    args.extend(config::container_args(module, operation));
+
+    // Pass through devices and bind mounts required by the module
+    // They might not exist yet before the module is loaded
+    if operation != config::Operation::Load {
+        // Older configuration files still bind-mount devices in 'container_args',
+        // which Podman refuses to combine with '--device' for the same path
+        let mounted = mount_destinations(&args);
+
+        args.extend(
+            device_args(module, operation)
+                .into_iter()
+                .filter(|arg| !matches!(arg.strip_prefix("--device="), Some(path) if mounted.iter().any(|m| m == path))),
+        );
+    }
+
+    // Confined containers can't read bind-mounted host paths with SELinux enforcing,
+    // and relabeling arbitrary host paths, like the current directory, isn't safe
+    let label_disable = String::from("--security-opt=label=disable");
+    let bind_mounts = args
+        .iter()
+        .any(|arg| arg.starts_with("--mount=type=bind") || arg.starts_with("--volume") || arg == "-v");
+
+    if bind_mounts && !config::privileged(module, operation) && !args.contains(&label_disable) {
+        args.push(label_disable);
+    }
+
+    // Run the command inside a new container
+    // Add additional Podman arguments from module configuration to the function
+    process::Command::new("podman")
+        .args(["run", "--rm"])
+        .args(args)
+        .arg(&image_name)
+        .args(command)
+        .status()
+        .expect("Error while running the container")
+}
+
+fn read_env_file(path: &str) -> Vec<String> {
+    let file = fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Error while reading environment file at {}", path));
+
+    // Each line is either 'KEY=VALUE', or just 'KEY' to pass through the host's value
+    file.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+fn run_options_args(module: &config::ModuleConfig, options: &RunOptions) -> Vec<String> {
+    let mut args = Vec::new();
+
+    // Forward stdin to the command, so that it can be used in pipelines
+    if options.interactive || fetch::is_stdin_readable() {
+        args.push(String::from("-i"));
+    }
+
+    // Only allocate a TTY if we are attached to one ourselves
+    if options.tty.unwrap_or_else(|| fetch::is_terminal(0) && fetch::is_terminal(1)) {
+        args.push(String::from("-t"));
+    }
+
+    // Later variables take precedence, so the module's environment
+    // comes first, followed by environment files and the command line
+    let mut vars: Vec<_> = module.env
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+
+    vars.sort();
+
+    for path in &options.env_files {
+        vars.extend(read_env_file(path));
+    }
+
+    vars.extend(options.env.iter().cloned());
+    args.extend(vars.iter().map(|var| format!("--env={}", var)));
+
+    // Make the current directory available at the same path inside the container
+    let mut workdir = options.workdir.clone();
+
+    if options.mount_cwd {
+        let cwd = env::current_dir().expect("Error while determining current directory");
+        let cwd = cwd.to_str().unwrap();
+
+        args.push(format!("--mount=type=bind,src={},dst={}", cwd, cwd));
+        workdir = workdir.or_else(|| Some(String::from(cwd)));
+    }
+
+    if let Some(workdir) = workdir {
+        args.push(format!("--workdir={}", workdir));
+    }
+
+    args
+}
+
+pub fn run(module: &config::ModuleConfig, command: &[String], options: &RunOptions, quiet: bool) -> i32 {
+    // Keep stdout clean for the output of the command itself
+    if !quiet {
+        eprintln!("Executing command {:?}, in module {} ...", command, module.name);
+    }
+
+    // Commands like 'zed' might run for as long as the system is up,
+    // and must not keep other podmod processes from pruning meanwhile
+    lock::release_global();
+
+    let status = run_container(module, config::Operation::Run, &run_options_args(module, options), command);
+
+    // Pass on the exit code of the command, like a shell would
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+pub fn shell(module: &config::ModuleConfig, shell: &str, options: &RunOptions) {
+    eprintln!("Starting shell session in module {} ...", module.name);
+
+    // Shell sessions might stay open for a long time, just like commands
+    lock::release_global();
+
+    // Start the shell inside a new container, interactive if possible
+    run_container(module, config::Operation::Shell, &run_options_args(module, options), &[String::from(shell)]);
+}
+
+fn get_shim_marker(path: &path::Path) -> Option<(String, String)> {
+    // Shims are recognized by a marker comment naming the module and command
+    let file = fs::read_to_string(path).ok()?;
+
+    file.lines()
+        .take(5)
+        .find_map(|line| line.strip_prefix("# podmod-shim: "))
+        .and_then(|marker| marker.split_once(' '))
+        .map(|(module, command)| (String::from(module), String::from(command)))
+}
+
+fn shell_quote(value: &str) -> String {
+    // Within single quotes, only single quotes themselves need escaping
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub fn shim_install(config_path: &str, module: &config::ModuleConfig, dir: &str, commands: &[String]) {
+    let podmod = env::current_exe().expect("Error while determining path to podmod");
+    let podmod = podmod.to_str().unwrap();
+
+    // The shims may be called from any working directory
+    let config_path = fs::canonicalize(config_path).expect("Error while determining path to configuration file");
+    let config_path = config_path.to_str().unwrap();
+
+    fs::create_dir_all(dir).unwrap_or_else(|_| panic!("Error while creating shim directory {}", dir));
+
+    // Command names end up in file paths and shell scripts
+    for command in commands {
+        let valid = command.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c));
+
+        if !valid || command.starts_with(['.', '-']) {
+            panic!("Invalid command name '{}', only plain file names are allowed", command);
+        }
+    }
+
+    for command in commands {
+        let path = format!("{}/{}", dir, command);
+
+        // Never overwrite files that weren't created by podmod
+        if path::Path::new(&path).exists() && get_shim_marker(path::Path::new(&path)).is_none() {
+            panic!("{} already exists and is not a podmod shim", path);
+        }
+
+        println!("Installing shim {} for module {} ...", path, module.name);
+
+        // 'podmod run' forwards arguments, stdin, and the exit code of the command
+        let shim = format!(
+            "#!/bin/sh\n\
+             # Generated by podmod, remove with 'podmod shim remove -m {module}'.\n\
+             # podmod-shim: {module} {command}\n\
+             exec {podmod} --config {config} run --quiet --module {quoted_module} -- {quoted_command} \"$@\"\n",
+            module = module.name,
+            command = command,
+            podmod = shell_quote(podmod),
+            config = shell_quote(config_path),
+            quoted_module = shell_quote(&module.name),
+            quoted_command = shell_quote(command),
+        );
+
+        fs::write(&path, shim).unwrap_or_else(|_| panic!("Error while writing shim {}", path));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .unwrap_or_else(|_| panic!("Error while making shim {} executable", path));
+    }
+}
+
+pub fn shim_list(dir: &str) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let mut shims: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| get_shim_marker(&entry.path()).map(|marker| (entry.path(), marker)))
+        .collect();
+
+    shims.sort();
+
+    for (path, (module, command)) in shims {
+        println!("{:<16} {:<16} {}", module, command, path.display());
+    }
+}
+
+pub fn shim_remove(module: &config::ModuleConfig, dir: &str, commands: &[String]) {
+    let entries = fs::read_dir(dir).unwrap_or_else(|_| panic!("Error while reading shim directory {}", dir));
+
+    let mut removed = Vec::new();
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let (shim_module, command) = match get_shim_marker(&entry.path()) {
+            Some(marker) => marker,
+            None => continue,
+        };
+
+        // Remove every shim of the module, unless only some commands are given
+        if shim_module != module.name || (!commands.is_empty() && !commands.contains(&command)) {
+            continue;
+        }
+
+        println!("Removing shim {} ...", entry.path().display());
+
+        fs::remove_file(entry.path())
+            .unwrap_or_else(|_| panic!("Error while removing shim {}", entry.path().display()));
+
+        removed.push(command);
+    }
+
+    for command in commands {
+        if !removed.contains(command) {
+            panic!("No shim for command {} of module {} in {}", command, module.name, dir);
+        }
+    }
+}
+
+pub fn status(module: &config::ModuleConfig) {
+    let loaded_modules = fetch::loaded_modules();
+
+    match load_state(module) {
+        LoadState::Loaded => println!("Module {} is loaded", module.name),
+        LoadState::PartiallyLoaded { .. } => println!("Module {} is partially loaded", module.name),
+        LoadState::NotLoaded => println!("Module {} is not loaded", module.name),
+    }
+
+    // List the state of each kernel object provided by the module
+    for object in &module.objects {
+        let name = config::object_name(object);
+        let state = if loaded_modules.contains(&name) { "loaded" } else { "not loaded" };
+
+        println!("  {:<16} {}", name, state);
+    }
+}
+
+pub fn sync(config: &config::Config, no_prune: bool) {
+    let kernel_versions = fetch::installed_kernels();
+    let mut built = false;
+    let mut failed = Vec::new();
+
+    // Only modules with a table in the configuration file are considered in use
+    // A broken module must not keep the other modules from being built
+    // The panic message has already been printed when it is caught
+    let modules: Vec<_> = supported_modules(config)
+        .into_iter()
+        .filter(|name| config.tree.get(name).is_some_and(|v| v.is_table()))
+        .filter_map(|name| match panic::catch_unwind(|| config::module(config, &name)) {
+            Ok(module) => Some(module),
+            Err(_) => {
+                eprintln!("Skipping module {} due to invalid configuration", name);
+                failed.push(name);
+                None
+            }
+        })
+        .collect();
+
+    for module in &modules {
+        for kernel_version in &kernel_versions {
+            let image_name = get_module_image_identifier(&module.name, &module.version, kernel_version);
+            let exists = image_exists(&image_name);
+
+            // Kernel objects extracted from an outdated image are refreshed, too
+            if exists && (module.scripts || is_cache_current(config, module, kernel_version)) {
+                continue;
+            }
+
+            if !is_kernel_supported(module, kernel_version) {
+                println!("Skipping module {} for unsupported kernel version {}", module.name, kernel_version);
+                continue;
+            }
+
+            // Lock the module like 'podmod build' does
+            // Prune only once after all builds are done
+            let result = panic::catch_unwind(|| {
+                let _lock = lock::exclusive(&module.name);
+                lock::global();
+
+                build(config, module, Some(kernel_version), true, true);
+            });
+
+            lock::release_global();
+
+            match result {
+                Ok(()) => built |= !exists,
+                Err(_) => {
+                    eprintln!("Failed to build module {} for kernel version {}", module.name, kernel_version);
+                    failed.push(format!("{} ({})", module.name, kernel_version));
+                }
+            }
+        }
+    }
+
+    if built && !no_prune {
+        prune();
+    }
+
+    if !failed.is_empty() {
+        panic!("Error while syncing modules {}", failed.join(", "));
+    }
+}
+
+pub fn unload(module: &config::ModuleConfig, idempotent: bool, force: bool) {
+    // Check if module is loaded
+    match load_state(module) {
+        LoadState::Loaded => {}
+        LoadState::PartiallyLoaded { loaded, .. } => {
+            // The unload script skips kernel objects that aren't resident
+            println!("Module {} is partially loaded, unloading {} ...", module.name, loaded.join(", "));
+        }
+        LoadState::NotLoaded => {
+            if idempotent {
+                return;
+            }
+
+            panic!("Module {} is not loaded", module.name);
+        }
+    }
+
+    // Refuse to unload a module that is still in use, which would
+    // otherwise fail halfway through removing its kernel objects
+    if !force {
+        let users = module_users(module);
+
+        if !users.is_empty() {
+            panic!("Module {} is in use ({}), use --force to unload it anyway", module.name, users.join("; "));
+        }
+    }
+
+    println!("Unloading module {} ...", module.name);
+
+    if module.scripts {
+        unload_with_script(module, force);
+    } else {
+        unload_natively(module, force);
+    }
+}
+
+fn unload_natively(module: &config::ModuleConfig, force: bool) {
+    let loaded_modules = fetch::loaded_modules();
+
+    // Remove each resident kernel object in reverse load order
+    for object in module.objects.iter().rev() {
+        let name = config::object_name(object);
+
+        if !loaded_modules.contains(&name) {
+            continue;
+        }
+
+        kmod::remove(&name, force)
+            .unwrap_or_else(|e| panic!("Error while removing kernel object {} ({})", name, e));
+    }
+}
+
+fn unload_with_script(module: &config::ModuleConfig, force: bool) {
+    // podmod's container images are always named predictably
+    let kernel_version = fetch::kernel_version();
+    let image_name = get_module_image_identifier(&module.name, &module.version, &kernel_version);
+
+    // Unloading only needs 'rmmod', so don't fail if the image
+    // has been removed since the module was loaded
+    if !image_exists(&image_name) {
+        println!("Image for module {} is missing, removing kernel objects directly ...", module.name);
+        unload_natively(module, force);
+        return;
+    }
+
+    // The unload script is expected to force removal if PODMOD_FORCE is set
+    let extra_args = if force { vec![String::from("--env=PODMOD_FORCE=1")] } else { Vec::new() };
+
+    eprintln!("Executing command {:?}, in module {} ...", ["unload"], module.name);
+
+    // Call the unload script inside a new container
+    // Add additional Podman arguments from module configuration, like for any other command
+    let status = run_container(module, config::Operation::Unload, &extra_args, &[String::from("unload")]);
+
+    if !status.success() {
+        panic!("Error while unloading module {}: unload script failed with {}", module.name, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_destinations_parses_mount_and_volume_args() {
+        let args: Vec<_> = ["--mount=type=bind,src=/dev/zfs,dst=/dev/zfs", "-v", "/srv:/mnt:ro", "--volume=/tmp:/tmp", "--privileged"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(mount_destinations(&args), vec!["/dev/zfs", "/mnt", "/tmp"]);
+    }
+
+    #[test]
+    fn compare_kernel_versions_ignores_release_suffix() {
+        assert_eq!(compare_kernel_versions("6.0.7-301.fc37.x86_64", "6.0.7"), cmp::Ordering::Equal);
+        assert_eq!(compare_kernel_versions("6.0.7+rpt-rpi-v8", "6.0.8"), cmp::Ordering::Less);
+    }
+
+    #[test]
+    fn compare_kernel_versions_stops_at_bound_precision() {
+        assert_eq!(compare_kernel_versions("6.0.7-301.fc37.x86_64", "6.0"), cmp::Ordering::Equal);
+        assert_eq!(compare_kernel_versions("6.1.2-200.fc37.x86_64", "6.0"), cmp::Ordering::Greater);
+        assert_eq!(compare_kernel_versions("5.19.16-200.fc36.x86_64", "6"), cmp::Ordering::Less);
+    }
+
+    #[test]
+    fn compare_kernel_versions_pads_missing_components() {
+        assert_eq!(compare_kernel_versions("6.0", "6.0.0"), cmp::Ordering::Equal);
+        assert_eq!(compare_kernel_versions("6.0", "6.0.1"), cmp::Ordering::Less);
+    }
 }
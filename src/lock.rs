@@ -0,0 +1,180 @@
+/*
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use nix::errno;
+use nix::fcntl;
+use nix::sys::stat;
+use nix::unistd;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::sync;
+use std::sync::atomic;
+
+const LOCK_DIR: &str = "/run/podmod";
+
+// Whether to wait for other podmod processes, or fail right away
+static WAIT: atomic::AtomicBool = atomic::AtomicBool::new(true);
+
+// The global lock is held until the process exits
+static GLOBAL: sync::Mutex<Option<fs::File>> = sync::Mutex::new(None);
+
+// The lock is released when the file is closed, i.e. when this is dropped
+#[derive(Debug)]
+pub struct Lock {
+    _file: fs::File,
+}
+
+fn open(path: &str) -> fs::File {
+    fs::create_dir_all(LOCK_DIR).expect("Error while creating lock directory");
+
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .unwrap_or_else(|_| panic!("Error while opening lock file at {}", path))
+}
+
+fn holders(file: &fs::File) -> Vec<String> {
+    let file_stat = match stat::fstat(file.as_raw_fd()) {
+        Ok(file_stat) => file_stat,
+        Err(_) => return Vec::new(),
+    };
+
+    // Locks are listed by device and inode of the locked file,
+    // e.g. '1: FLOCK  ADVISORY  READ 1234 00:1a:5678 0 EOF'
+    let id = format!(
+        "{:02x}:{:02x}:{}",
+        stat::major(file_stat.st_dev),
+        stat::minor(file_stat.st_dev),
+        file_stat.st_ino,
+    );
+
+    let own_pid = unistd::getpid().to_string();
+    let locks = fs::read_to_string("/proc/locks").unwrap_or_default();
+
+    let mut pids: Vec<_> = locks
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        // Waiting processes are marked with '->' and don't hold the lock
+        .filter(|fields| fields.len() > 5 && fields[1] != "->" && fields[5] == id)
+        .map(|fields| String::from(fields[4]))
+        .filter(|pid| *pid != own_pid)
+        .collect();
+
+    pids.sort();
+    pids.dedup();
+    pids
+}
+
+fn acquire(file: &fs::File, path: &str, exclusive: bool) {
+    let (blocking, nonblocking) = if exclusive {
+        (fcntl::FlockArg::LockExclusive, fcntl::FlockArg::LockExclusiveNonblock)
+    } else {
+        (fcntl::FlockArg::LockShared, fcntl::FlockArg::LockSharedNonblock)
+    };
+
+    match fcntl::flock(file.as_raw_fd(), nonblocking) {
+        Ok(()) => return,
+        Err(errno::Errno::EWOULDBLOCK) => {}
+        Err(e) => panic!("Error while acquiring lock at {} ({})", path, e),
+    }
+
+    let pids = holders(file);
+    let pids = if pids.is_empty() { String::from("unknown") } else { pids.join(", ") };
+
+    if !WAIT.load(atomic::Ordering::Relaxed) {
+        panic!("Another podmod process is running (lock at {} is held by PID {})", path, pids);
+    }
+
+    eprintln!("Waiting for another podmod process (lock at {} is held by PID {}) ...", path, pids);
+
+    fcntl::flock(file.as_raw_fd(), blocking)
+        .unwrap_or_else(|e| panic!("Error while acquiring lock at {} ({})", path, e));
+}
+
+pub fn set_wait(wait: bool) {
+    WAIT.store(wait, atomic::Ordering::Relaxed);
+}
+
+// To prevent deadlocks, the lock of a module has to be acquired before the global
+// lock, and no other lock may be waited for while holding the lock of the builder images
+pub fn global() {
+    let mut global = GLOBAL.lock().unwrap();
+
+    if global.is_some() {
+        return;
+    }
+
+    let path = format!("{}/lock", LOCK_DIR);
+    let file = open(&path);
+
+    acquire(&file, &path, false);
+
+    *global = Some(file);
+}
+
+pub fn release_global() {
+    GLOBAL.lock().unwrap().take();
+}
+
+pub fn exclusive(name: &str) -> Lock {
+    let path = format!("{}/{}.lock", LOCK_DIR, name);
+    let file = open(&path);
+
+    acquire(&file, &path, true);
+
+    Lock { _file: file }
+}
+
+pub fn with_exclusive_global(f: impl FnOnce()) -> bool {
+    let mut global = GLOBAL.lock().unwrap();
+    let path = format!("{}/lock", LOCK_DIR);
+
+    // Release the shared lock held by this process first, as two processes
+    // upgrading their shared locks at the same time would block each other
+    let was_shared = global.is_some();
+
+    let file = match global.take() {
+        Some(file) => {
+            fcntl::flock(file.as_raw_fd(), fcntl::FlockArg::Unlock)
+                .unwrap_or_else(|e| panic!("Error while releasing lock at {} ({})", path, e));
+            file
+        }
+        None => open(&path),
+    };
+
+    // Never wait for the exclusive lock, as new shared holders could keep it from ever being granted
+    let acquired = match fcntl::flock(file.as_raw_fd(), fcntl::FlockArg::LockExclusiveNonblock) {
+        Ok(()) => true,
+        Err(errno::Errno::EWOULDBLOCK) => false,
+        Err(e) => panic!("Error while acquiring lock at {} ({})", path, e),
+    };
+
+    if acquired {
+        f();
+    }
+
+    // Keep holding the shared lock until the process exits, like before
+    // This only waits for a concurrent holder of the exclusive lock, regardless of '--no-wait'
+    if was_shared {
+        fcntl::flock(file.as_raw_fd(), fcntl::FlockArg::LockShared)
+            .unwrap_or_else(|e| panic!("Error while acquiring lock at {} ({})", path, e));
+
+        *global = Some(file);
+    }
+
+    acquired
+}
@@ -18,9 +18,36 @@ use nix::unistd;
 use podmod::config;
 use std::env;
 use std::path;
+use std::process;
 
 pub mod cli;
 
+fn run_options(
+    env: Vec<String>,
+    env_files: Vec<String>,
+    interactive: bool,
+    mount_cwd: bool,
+    no_tty: bool,
+    tty: bool,
+    workdir: Option<String>,
+) -> podmod::RunOptions {
+    // Detect whether to allocate a TTY unless told otherwise
+    let tty = match (tty, no_tty) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+
+    podmod::RunOptions {
+        tty,
+        interactive,
+        env,
+        env_files,
+        workdir,
+        mount_cwd,
+    }
+}
+
 fn main() {
     // Ensure running on Linux
     if env::consts::OS != "linux" {
@@ -33,11 +60,18 @@ fn main() {
 
     let module_config = match args.command {
         cli::Command::Build { ref module, .. } |
+        cli::Command::Export { ref module, .. } |
+        cli::Command::Info { ref module, .. } |
         cli::Command::Load { ref module, .. } |
+        cli::Command::Params { ref module, .. } |
+        cli::Command::Reload { ref module, .. } |
         cli::Command::Run { ref module, .. } |
         cli::Command::Shell { ref module, .. } |
+        cli::Command::Shim { action: cli::ShimAction::Install { ref module, .. } } |
+        cli::Command::Shim { action: cli::ShimAction::Remove { ref module, .. } } |
+        cli::Command::Status { ref module, .. } |
         cli::Command::Unload { ref module, .. } => {
-            Some(config::module(&config.tree, &module))
+            Some(config::module(&config, module))
         }
         _ => None,
     };
@@ -52,25 +86,96 @@ fn main() {
         panic!("Data directory does not exist")
     }
 
+    // Serialize operations on the same module, and keep other podmod
+    // processes from pruning images while this one is running
+    // Generators must not block, so units are generated without locking
+    podmod::lock::set_wait(!args.no_wait);
+
+    // Sync locks each module itself while building it
+    let _lock = match args.command {
+        cli::Command::GenerateUnits { .. } |
+        cli::Command::Sync { .. } => None,
+        cli::Command::Build { ref module, .. } |
+        cli::Command::Load { ref module, .. } |
+        cli::Command::Reload { ref module, .. } |
+        cli::Command::Unload { ref module, .. } => {
+            let lock = podmod::lock::exclusive(module);
+            podmod::lock::global();
+            Some(lock)
+        }
+        _ => {
+            podmod::lock::global();
+            None
+        }
+    };
+
     // Call appropriate function from library
     match args.command {
-        cli::Command::Build { idempotent, no_prune, .. } => {
-            podmod::build(&config, &module_config.unwrap(), idempotent, no_prune)
+        cli::Command::Build { idempotent, ref kernel, no_prune, .. } => {
+            podmod::build(&config, &module_config.unwrap(), kernel.as_deref(), idempotent, no_prune)
         },
-        cli::Command::Load { idempotent, .. } => {
-            podmod::load(&module_config.unwrap(), idempotent)
+        cli::Command::Export { ref kernel, ref output, userland, .. } => {
+            podmod::export(&config, &module_config.unwrap(), kernel.as_deref(), output, userland)
+        },
+        cli::Command::GenerateUnits { ref normal_dir, .. } => {
+            podmod::generate_units(&config, normal_dir)
+        },
+        cli::Command::Info { json, .. } => {
+            podmod::info(&config, &module_config.unwrap(), json)
+        },
+        cli::Command::Load { build, idempotent, params, replace_params, args, .. } => {
+            let mut module_config = module_config.unwrap();
+
+            // Kernel parameters from the command line take precedence
+            if replace_params {
+                module_config.kernel_args.clear();
+            }
+
+            for arg in params.into_iter().chain(args) {
+                config::set_kernel_arg(&mut module_config.kernel_args, arg);
+            }
+
+            podmod::load(&config, &module_config, idempotent, build)
         },
         cli::Command::Modules {} => {
             podmod::modules(&config)
         },
-        cli::Command::Run { command, .. } => {
-            podmod::run(&module_config.unwrap(), &command);
+        cli::Command::Params { action: None, .. } => {
+            podmod::params(&module_config.unwrap())
+        },
+        cli::Command::Params { action: Some(cli::ParamsAction::Set { persist, assignments }), .. } => {
+            podmod::set_params(&args.config, &module_config.unwrap(), &assignments, persist)
         },
-        cli::Command::Shell { shell, .. } => {
-            podmod::shell(&module_config.unwrap(), &shell);
+        cli::Command::Reload { preserve_params, .. } => {
+            podmod::reload(&config, &module_config.unwrap(), preserve_params)
+        },
+        cli::Command::Run { command, env, env_file, interactive, mount_cwd, no_tty, quiet, tty, workdir, .. } => {
+            let options = run_options(env, env_file, interactive, mount_cwd, no_tty, tty, workdir);
+            process::exit(podmod::run(&module_config.unwrap(), &command, &options, quiet));
+        },
+        cli::Command::Shell { shell, env, env_file, interactive, mount_cwd, no_tty, tty, workdir, .. } => {
+            let options = run_options(env, env_file, interactive, mount_cwd, no_tty, tty, workdir);
+            podmod::shell(&module_config.unwrap(), &shell, &options);
         }
-        cli::Command::Unload { idempotent, .. } => {
-            podmod::unload(&module_config.unwrap(), idempotent)
+        cli::Command::Shim { action: cli::ShimAction::Install { ref commands, ref dir, .. } } => {
+            let dir = dir.as_deref().unwrap_or(&config.shim_dir);
+            podmod::shim_install(&args.config, &module_config.unwrap(), dir, commands)
+        },
+        cli::Command::Shim { action: cli::ShimAction::List { ref dir } } => {
+            podmod::shim_list(dir.as_deref().unwrap_or(&config.shim_dir))
+        },
+        cli::Command::Shim { action: cli::ShimAction::Remove { ref commands, ref dir, .. } } => {
+            let dir = dir.as_deref().unwrap_or(&config.shim_dir);
+            podmod::shim_remove(&module_config.unwrap(), dir, commands)
+        },
+        cli::Command::Status { .. } => {
+            podmod::status(&module_config.unwrap())
+        },
+        cli::Command::Sync { no_prune } => {
+            podmod::sync(&config, no_prune)
+        },
+        cli::Command::Unload { force, idempotent, .. } => {
+            podmod::unload(&module_config.unwrap(), idempotent, force)
         }
     };
 }
@@ -0,0 +1,158 @@
+/*
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::process;
+use std::str;
+
+#[derive(Clone, Debug, Default)]
+pub struct Parameter {
+    pub name: String,
+    pub description: String,
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ModInfo {
+    pub name: String,
+    pub version: String,
+    pub vermagic: String,
+    pub license: String,
+    pub signer: String,
+    pub depends: Vec<String>,
+    pub parameters: Vec<Parameter>,
+}
+
+fn parse_parameter(value: &str) -> Parameter {
+    // Parameters are formatted as 'NAME:DESCRIPTION (TYPE)'
+    let (name, rest) = value.split_once(':').unwrap_or((value, ""));
+    let rest = rest.trim();
+
+    let (description, kind) = match rest.rfind(" (").or_else(|| rest.find('(')) {
+        Some(i) if rest.ends_with(')') => (rest[..i].trim(), &rest[i..]),
+        _ => (rest, ""),
+    };
+
+    let kind = kind.trim().trim_start_matches('(').trim_end_matches(')');
+
+    Parameter {
+        name: String::from(name.trim()),
+        description: String::from(description),
+        kind: String::from(kind),
+    }
+}
+
+pub fn modinfo(path: &str) -> ModInfo {
+    // Call 'modinfo' to read the '.modinfo' section of the kernel object
+    let output = process::Command::new("modinfo")
+        .arg(path)
+        .output()
+        .expect("Error while fetching kernel object information");
+
+    if !output.status.success() {
+        panic!("Error while fetching kernel object information of {}", path);
+    }
+
+    let output = str::from_utf8(&output.stdout).unwrap();
+    let mut info = ModInfo::default();
+
+    for line in output.lines() {
+        // Each field starts at the beginning of the line as 'KEY: VALUE'
+        // Indented lines continue a multi-line field, such as a signature
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) if !key.is_empty() && !key.starts_with(char::is_whitespace) => (key, value.trim()),
+            _ => continue,
+        };
+
+        match key {
+            "name" => info.name = String::from(value),
+            "version" => info.version = String::from(value),
+            "vermagic" => info.vermagic = String::from(value),
+            "license" => info.license = String::from(value),
+            "signer" => info.signer = String::from(value),
+            "depends" => {
+                info.depends = value
+                    .split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            "parm" => info.parameters.push(parse_parameter(value)),
+            _ => {}
+        }
+    }
+
+    info
+}
+
+pub fn to_json(info: &ModInfo) -> serde_json::Value {
+    let parameters: Vec<_> = info.parameters
+        .iter()
+        .map(|p| serde_json::json!({
+            "name": p.name,
+            "description": p.description,
+            "type": p.kind,
+        }))
+        .collect();
+
+    serde_json::json!({
+        "name": info.name,
+        "version": info.version,
+        "vermagic": info.vermagic,
+        "license": info.license,
+        "signer": info.signer,
+        "depends": info.depends,
+        "parameters": parameters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_parameter_splits_description_and_type() {
+        let parameter = parse_parameter("exclusive_caps:whether to announce OUTPUT/CAPTURE capabilities exclusively (array of bool)");
+
+        assert_eq!(parameter.name, "exclusive_caps");
+        assert_eq!(parameter.description, "whether to announce OUTPUT/CAPTURE capabilities exclusively");
+        assert_eq!(parameter.kind, "array of bool");
+    }
+
+    #[test]
+    fn parse_parameter_keeps_parentheses_in_description() {
+        let parameter = parse_parameter("zfs_arc_max:Max arc size (bytes) (ulong)");
+
+        assert_eq!(parameter.description, "Max arc size (bytes)");
+        assert_eq!(parameter.kind, "ulong");
+    }
+
+    #[test]
+    fn parse_parameter_accepts_missing_type() {
+        let parameter = parse_parameter("debug:Enable debugging");
+
+        assert_eq!(parameter.name, "debug");
+        assert_eq!(parameter.description, "Enable debugging");
+        assert_eq!(parameter.kind, "");
+    }
+
+    #[test]
+    fn parse_parameter_accepts_missing_description() {
+        let parameter = parse_parameter("spl_hostid:(ulong)");
+
+        assert_eq!(parameter.name, "spl_hostid");
+        assert_eq!(parameter.description, "");
+        assert_eq!(parameter.kind, "ulong");
+    }
+}
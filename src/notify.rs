@@ -0,0 +1,46 @@
+/*
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net;
+
+fn notify(state: &str) {
+    // Only set when running as a Type=notify systemd service
+    let socket_path = match env::var("NOTIFY_SOCKET") {
+        Ok(socket_path) => socket_path,
+        Err(_) => return,
+    };
+
+    // A leading '@' denotes a socket in the abstract namespace
+    let address = match socket_path.strip_prefix('@') {
+        Some(name) => net::SocketAddr::from_abstract_name(name),
+        None => net::SocketAddr::from_pathname(&socket_path),
+    };
+
+    // Notifications are informational only, so failing to send them
+    // must not interrupt the actual operation
+    if let (Ok(address), Ok(socket)) = (address, net::UnixDatagram::unbound()) {
+        let _ = socket.send_to_addr(state.as_bytes(), &address);
+    }
+}
+
+pub fn ready() {
+    notify("READY=1");
+}
+
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
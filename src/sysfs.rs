@@ -0,0 +1,83 @@
+/*
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub value: Option<String>,
+    pub writable: bool,
+}
+
+pub fn holders(module: &str) -> Vec<String> {
+    // Every module depending on this one has an entry in 'holders/'
+    let entries = match fs::read_dir(format!("/sys/module/{}/holders", module)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut holders: Vec<_> = entries
+        .map(|entry| entry.expect("Error while reading module holders"))
+        .map(|entry| String::from(entry.file_name().to_str().unwrap()))
+        .collect();
+
+    holders.sort();
+    holders
+}
+
+pub fn parameters(module: &str) -> Vec<Parameter> {
+    // Each parameter of a loaded module is exposed as a file in sysfs
+    // Modules without parameters don't have the directory at all
+    let entries = match fs::read_dir(format!("/sys/module/{}/parameters", module)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut parameters: Vec<_> = entries
+        .map(|entry| entry.expect("Error while reading module parameters"))
+        .map(|entry| {
+            let name = String::from(entry.file_name().to_str().unwrap());
+
+            // Some parameters are write-only, and can't be read back
+            let value = fs::read_to_string(entry.path())
+                .ok()
+                .map(|v| String::from(v.trim_end_matches('\n')));
+
+            // The file is only writable if the parameter may be changed at runtime
+            let mode = entry.metadata().map(|m| m.permissions().mode()).unwrap_or(0);
+            let writable = mode & 0o222 != 0;
+
+            Parameter { name, value, writable }
+        })
+        .collect();
+
+    parameters.sort_by(|a, b| a.name.cmp(&b.name));
+    parameters
+}
+
+pub fn refcount(module: &str) -> u32 {
+    // Built-in modules don't have a reference count
+    fs::read_to_string(format!("/sys/module/{}/refcnt", module))
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn set_parameter(module: &str, parameter: &str, value: &str) -> io::Result<()> {
+    fs::write(format!("/sys/module/{}/parameters/{}", module, parameter), value)
+}